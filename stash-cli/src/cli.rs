@@ -13,8 +13,36 @@ pub struct Cli {
 pub enum Cmd {
     /// Generate a new keypair
     Keygen,
+    /// Show server protocol version and capabilities
+    Info,
     /// List tags
-    Tags,
+    Tags {
+        /// Show the file count and total size per tag
+        #[arg(long, default_value_t = false)]
+        stats: bool,
+    },
+    /// Rename a tag
+    RenameTag {
+        /// Current tag name
+        from: String,
+        /// New tag name
+        to: String,
+    },
+    /// Move all files from one tag to another and delete the first
+    MergeTags {
+        /// Tag to merge away
+        from: String,
+        /// Tag to merge into
+        into: String,
+    },
+    /// Delete a tag from all files
+    DeleteTag {
+        /// Tag name
+        name: String,
+        /// Delete even if files would be left without tags
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
     /// Upload a file
     Upload {
         /// Local file path
@@ -27,6 +55,15 @@ pub enum Cmd {
         /// Replace existing file?
         #[arg(long, default_value_t = false)]
         replace: bool,
+        /// Upload through a blob that can be resumed if interrupted
+        #[arg(long, default_value_t = false)]
+        resumable: bool,
+        /// Resume an interrupted upload into this blob
+        #[arg(long)]
+        resume: Option<String>,
+        /// Number of ranges to transfer concurrently, at most 16
+        #[arg(long, default_value_t = 1)]
+        parallel: usize,
     },
     /// Download a file
     Download {
@@ -34,19 +71,121 @@ pub enum Cmd {
         path: PathBuf,
         /// Remote file name
         name: String,
+        /// Download this version instead of the current one
+        #[arg(long)]
+        version: Option<u64>,
+        /// Number of ranges to transfer concurrently
+        #[arg(long, default_value_t = 1)]
+        parallel: usize,
     },
     /// Read a file, printing its contents to stdout
     Read {
         /// Remote file name
         name: String,
+        /// Read this version instead of the current one
+        #[arg(long)]
+        version: Option<u64>,
+        /// Number of ranges to transfer concurrently
+        #[arg(long, default_value_t = 1)]
+        parallel: usize,
     },
-    /// Delete a file
+    /// Move a file and all of its versions to the trash
     Delete {
         /// Remote file name
         name: String,
     },
+    /// Rename a file, keeping its versions
+    Mv {
+        /// Current remote file name
+        from: String,
+        /// New remote file name
+        to: String,
+        /// Move an existing file with the new name to the trash?
+        #[arg(long, default_value_t = false)]
+        replace: bool,
+    },
+    /// Copy a file without transferring its content
+    Cp {
+        /// Remote file name to copy
+        from: String,
+        /// Remote file name of the copy
+        to: String,
+        /// Tags of the copy (comma-separated, defaults to the source's tags)
+        #[arg(long, use_value_delimiter = true, value_delimiter = ',')]
+        tags: Option<Vec<String>>,
+    },
+    /// Add or remove tags on files
+    Retag {
+        /// Remote file names
+        #[arg(required_unless_present = "search")]
+        names: Vec<String>,
+        /// Tags to add (comma-separated)
+        #[arg(long, use_value_delimiter = true, value_delimiter = ',')]
+        add: Vec<String>,
+        /// Tags to remove (comma-separated)
+        #[arg(long, use_value_delimiter = true, value_delimiter = ',')]
+        remove: Vec<String>,
+        /// Edit every file matching a tag and search term instead
+        #[arg(long, num_args = 2, value_names = ["TAG", "TERM"], conflicts_with = "names")]
+        search: Option<Vec<String>>,
+    },
+    /// List deleted files
+    Trash,
+    /// Restore a deleted file from the trash
+    Undelete {
+        /// Trash entry id
+        id: u64,
+        /// Restore under this name instead of the original one
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Permanently remove deleted files
+    Purge {
+        /// Trash entry id
+        id: Option<u64>,
+        /// Purge the whole trash?
+        #[arg(long, default_value_t = false, conflicts_with = "id")]
+        all: bool,
+    },
+    /// List the versions of a file
+    Versions {
+        /// Remote file name
+        name: String,
+    },
+    /// Make an old version of a file current again
+    Restore {
+        /// Remote file name
+        name: String,
+        /// Version to restore
+        version: u64,
+    },
+    /// Set how many versions of a file are kept
+    KeepVersions {
+        /// Remote file name
+        name: String,
+        /// Number of versions, including the current one (server default if omitted)
+        keep: Option<u32>,
+    },
+    /// List in-flight blobs (admin only)
+    Blobs,
     /// GC blob store
     GcBlobs,
+    /// Show storage statistics
+    Stats,
+    /// Rewrap stored data keys under the server's current master key
+    RotateKeys,
+    /// Verify stored content against the catalog
+    Scrub {
+        /// Move corrupted and orphaned content into quarantine?
+        #[arg(long, default_value_t = false)]
+        quarantine: bool,
+        /// Number of content objects to check per request
+        #[arg(long, default_value_t = 1000)]
+        batch: u32,
+        /// Resume after this content hash (optional)
+        #[arg(long)]
+        after: Option<String>,
+    },
     /// List files
     List {
         /// Tag
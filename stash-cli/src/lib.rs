@@ -1,17 +1,37 @@
 mod cli;
 mod config;
 
-use std::{fmt::Write, os::unix::fs::MetadataExt, path::PathBuf, str::FromStr};
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    io::SeekFrom,
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 use iroh::{Endpoint, NodeId, SecretKey};
-use stash::{Client, File, Tag};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use stash::{
+    BlobId, Client, ContentHash, Failure, File, FileDescription, FileSelection, Hasher,
+    HashingReader, Response, Tag,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    task::JoinHandle,
+};
 
 pub use cli::{Cli, Cmd};
 pub use config::Config;
 
-const CHUNK_SIZE: usize = 5_000_000;
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+const APPEND_CHUNK_SIZE: usize = 4_000_000;
+const MAX_APPEND_RETRIES: usize = 5;
+const MAX_UPLOAD_PARALLEL: usize = 16;
+const DOWNLOAD_RANGE_SIZE: u64 = 4_000_000;
+
+type Transfers<T> = VecDeque<JoinHandle<anyhow::Result<T>>>;
 
 pub async fn exec(sk: SecretKey, server: NodeId, cmd: Cmd) -> anyhow::Result<()> {
     let endpoint = Endpoint::builder()
@@ -21,23 +41,70 @@ pub async fn exec(sk: SecretKey, server: NodeId, cmd: Cmd) -> anyhow::Result<()>
         .await?;
 
     let client = Client::new(endpoint, server);
+    let conn = client.clone();
 
-    match cmd {
+    let rsp = match cmd {
         Cmd::Keygen => keygen().await,
-        Cmd::Tags => tags(client).await,
+        Cmd::Info => info(client).await,
+        Cmd::Tags { stats } => tags(client, stats).await,
+        Cmd::RenameTag { from, to } => rename_tag(client, from, to).await,
+        Cmd::MergeTags { from, into } => merge_tags(client, from, into).await,
+        Cmd::DeleteTag { name, force } => delete_tag(client, name, force).await,
         Cmd::Upload {
             path,
             name,
             tags,
             replace,
-        } => upload(client, path, name, tags, replace).await,
-        Cmd::Download { path, name } => download(client, path, name).await,
-        Cmd::Read { name } => read(client, name).await,
+            resumable,
+            resume,
+            parallel,
+        } => {
+            upload(
+                client, path, name, tags, replace, resumable, resume, parallel,
+            )
+            .await
+        }
+        Cmd::Download {
+            path,
+            name,
+            version,
+            parallel,
+        } => download(client, path, name, version, parallel).await,
+        Cmd::Read {
+            name,
+            version,
+            parallel,
+        } => read(client, name, version, parallel).await,
         Cmd::Delete { name } => delete(client, name).await,
+        Cmd::Mv { from, to, replace } => mv(client, from, to, replace).await,
+        Cmd::Cp { from, to, tags } => cp(client, from, to, tags).await,
+        Cmd::Retag {
+            names,
+            add,
+            remove,
+            search,
+        } => retag(client, names, add, remove, search).await,
+        Cmd::Trash => trash(client).await,
+        Cmd::Undelete { id, name } => undelete(client, id, name).await,
+        Cmd::Purge { id, all } => purge(client, id, all).await,
+        Cmd::Versions { name } => versions(client, name).await,
+        Cmd::Restore { name, version } => restore(client, name, version).await,
+        Cmd::KeepVersions { name, keep } => keep_versions(client, name, keep).await,
+        Cmd::Blobs => blobs(client).await,
         Cmd::GcBlobs => gc_blobs(client).await,
+        Cmd::Stats => stats(client).await,
+        Cmd::RotateKeys => rotate_keys(client).await,
+        Cmd::Scrub {
+            quarantine,
+            batch,
+            after,
+        } => scrub(client, quarantine, batch, after).await,
         Cmd::List { tag, prefix } => list(client, tag, prefix).await,
         Cmd::Search { tag, term } => search(client, tag, term).await,
-    }
+    };
+
+    conn.close().await;
+    rsp
 }
 
 pub async fn keygen() -> anyhow::Result<()> {
@@ -51,19 +118,67 @@ pub async fn keygen() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn tags(client: Client) -> anyhow::Result<()> {
+async fn info(client: Client) -> anyhow::Result<()> {
+    let info = client.server_info().await?;
+
+    println!("Protocol: {}", info.version);
+    println!("Capabilities: {}", info.capabilities.join(", "));
+    Ok(())
+}
+
+async fn tags(client: Client, stats: bool) -> anyhow::Result<()> {
+    if stats {
+        let tags = client.tag_stats().await?.res()?;
+        for tag in tags.iter() {
+            println!("{}\t{}\t{}", tag.name, tag.files, tag.size);
+        }
+
+        return Ok(());
+    }
+
     let tags = client.tags().await?.res()?;
 
     println!("{}", tags.join("\n"));
     Ok(())
 }
 
+async fn rename_tag(client: Client, from: String, to: String) -> anyhow::Result<()> {
+    client
+        .rename_tag(parse_tag(&from)?, parse_tag(&to)?)
+        .await?
+        .res()?;
+
+    println!("Renamed {from} to {to}");
+    Ok(())
+}
+
+async fn merge_tags(client: Client, from: String, into: String) -> anyhow::Result<()> {
+    client
+        .merge_tags(parse_tag(&from)?, parse_tag(&into)?)
+        .await?
+        .res()?;
+
+    println!("Merged {from} into {into}");
+    Ok(())
+}
+
+async fn delete_tag(client: Client, name: String, force: bool) -> anyhow::Result<()> {
+    client.delete_tag(parse_tag(&name)?, force).await?.res()?;
+
+    println!("Deleted {name}");
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn upload(
     client: Client,
     path: PathBuf,
     name: String,
     tags: Vec<String>,
     replace: bool,
+    resumable: bool,
+    resume: Option<String>,
+    parallel: usize,
 ) -> anyhow::Result<()> {
     let tags = tags
         .iter()
@@ -76,58 +191,360 @@ async fn upload(
 
     let mut file = tokio::fs::File::open(path).await?;
     let meta = file.metadata().await?;
-    let blob = client.create_blob().await?.res()?;
 
-    let mut written = 0;
+    if parallel > MAX_UPLOAD_PARALLEL {
+        return Err(anyhow::anyhow!(
+            "At most {MAX_UPLOAD_PARALLEL} ranges can be uploaded concurrently"
+        ));
+    }
+
+    // A resumed blob may have been written out of order, whatever --parallel is now
+    let parallel = parallel.max(1);
+    let write_blob =
+        (parallel > 1 || resume.is_some()) && client.server_info().await?.supports("write-blob");
+
+    let mut hash = None;
+    if resume.is_none() && client.server_info().await?.supports("dedup") {
+        let file_hash = hash_file(&mut file).await?;
+        if client.has_content(file_hash.clone()).await?.res()? {
+            let file = client
+                .commit_hash(file_hash, name, tags, replace)
+                .await?
+                .res()?;
+
+            println!("{}", display_file(&file));
+            return Ok(());
+        }
+
+        file.seek(SeekFrom::Start(0)).await?;
+        hash = Some(file_hash);
+    }
+
+    let blob = match resume {
+        Some(blob) => {
+            Some(BlobId::from_str(&blob).map_err(|_| anyhow::anyhow!("Invalid blob {blob}"))?)
+        }
+        None if resumable || write_blob => {
+            let blob = client.create_blob().await?.res()?.name;
+            if resumable {
+                eprintln!("Blob: {blob}");
+            }
+
+            Some(blob)
+        }
+        None => None,
+    };
+
     let progress = progress_bar(meta.size());
+    let file = match blob {
+        Some(blob) => {
+            let written = if write_blob {
+                write_file(&client, &blob, file, meta.size(), parallel, &progress).await
+            } else {
+                append_file(&client, &blob, file, meta.size(), &progress).await
+            };
+
+            let hash = match written {
+                Ok(hash) => hash,
+                Err(e) => {
+                    eprintln!("Upload interrupted, resume with --resume {blob}");
+                    return Err(e);
+                }
+            };
+
+            client
+                .commit_blob(blob, name, tags, replace, Some(hash), Some(meta.size()))
+                .await?
+                .res()?
+        }
+        None => {
+            let mut reader = HashingReader::new(progress.wrap_async_read(file));
+            let file = client
+                .upload(name, tags, replace, meta.size(), hash, &mut reader)
+                .await?
+                .res()?;
+
+            let hash = reader.finalize();
+            if file.hash != hash {
+                return Err(anyhow::anyhow!(
+                    "Server stored hash {}, expected {hash}",
+                    file.hash
+                ));
+            }
+
+            file
+        }
+    };
+
+    progress.finish();
+
+    println!("{}", display_file(&file));
+    Ok(())
+}
+
+async fn append_file(
+    client: &Client,
+    blob: &BlobId,
+    mut file: tokio::fs::File,
+    size: u64,
+    progress: &ProgressBar,
+) -> anyhow::Result<ContentHash> {
+    let mut offset = client.describe_blob(blob.clone()).await?.res()?.size;
+    if offset > size {
+        return Err(anyhow::anyhow!("Blob is larger than the local file"));
+    }
+
+    let mut hasher = Hasher::default();
+    let mut buf = vec![0; APPEND_CHUNK_SIZE];
+    hash_prefix(&mut file, offset, &mut hasher, &mut buf).await?;
+
+    let mut hashed = offset;
+    let mut retries = 0;
+    while offset < size {
+        progress.set_position(offset);
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        let len = APPEND_CHUNK_SIZE.min((size - offset) as usize);
+        file.read_exact(&mut buf[0..len]).await?;
+
+        let end = offset + len as u64;
+        if end > hashed {
+            hasher.update(&buf[(hashed - offset) as usize..len]);
+            hashed = end;
+        }
+
+        match client
+            .append_blob(blob.clone(), offset, buf[0..len].to_vec())
+            .await
+        {
+            Ok(Response::Ok(blob)) => {
+                offset = blob.size;
+                retries = 0;
+            }
+            Ok(Response::Err(Failure::Conflict(_))) | Err(_) if retries < MAX_APPEND_RETRIES => {
+                retries += 1;
+                offset = client.describe_blob(blob.clone()).await?.res()?.size;
+            }
+            rsp => {
+                rsp?.res()?;
+            }
+        }
+    }
+
+    progress.set_position(size);
+    Ok(hasher.finalize())
+}
+
+async fn write_file(
+    client: &Client,
+    blob: &BlobId,
+    mut file: tokio::fs::File,
+    size: u64,
+    parallel: usize,
+    progress: &ProgressBar,
+) -> anyhow::Result<ContentHash> {
+    let blob_size = client.describe_blob(blob.clone()).await?.res()?.size;
+    if blob_size > size {
+        return Err(anyhow::anyhow!("Blob is larger than the local file"));
+    }
+
+    // Writes may land out of order, so an interrupted upload can leave holes in
+    // the ranges that were in flight before the blob size. Any earlier run had at
+    // most `MAX_UPLOAD_PARALLEL` of them, so rewrite that window.
+    let range = APPEND_CHUNK_SIZE as u64;
+    let window = MAX_UPLOAD_PARALLEL as u64 * range;
+    let mut offset = blob_size.saturating_sub(window) / range * range;
+
+    let mut hasher = Hasher::default();
+    let mut buf = vec![0; APPEND_CHUNK_SIZE];
+    hash_prefix(&mut file, offset, &mut hasher, &mut buf).await?;
+    progress.set_position(offset);
 
-    let mut buf = vec![0; CHUNK_SIZE];
+    let mut pending = Transfers::new();
     loop {
-        progress.set_position(written as u64);
+        while offset < size && pending.len() < parallel {
+            let len = range.min(size - offset);
+            let mut data = vec![0; len as usize];
+            file.read_exact(&mut data).await?;
+            hasher.update(&data);
+
+            pending.push_back(tokio::spawn(write_range(
+                client.clone(),
+                blob.clone(),
+                offset,
+                data,
+            )));
+            offset += len;
+        }
 
-        let n = file.read(&mut buf).await?;
-        if n == 0 {
+        let Some(handle) = pending.pop_front() else {
             break;
+        };
+
+        progress.inc(join(handle, &mut pending).await?);
+    }
+
+    Ok(hasher.finalize())
+}
+
+async fn write_range(
+    client: Client,
+    blob: BlobId,
+    offset: u64,
+    data: Vec<u8>,
+) -> anyhow::Result<u64> {
+    let mut retries = 0;
+    loop {
+        match client.write_blob(blob.clone(), offset, data.clone()).await {
+            Ok(rsp) => {
+                rsp.res()?;
+                return Ok(data.len() as u64);
+            }
+            Err(_) if retries < MAX_APPEND_RETRIES => retries += 1,
+            Err(e) => return Err(e.into()),
         }
+    }
+}
 
-        client
-            .append_blob(blob.name.clone(), buf[0..n].to_vec())
+async fn fetch<W: AsyncWrite + Unpin>(
+    client: &Client,
+    file: &FileDescription,
+    parallel: usize,
+    writer: &mut W,
+    progress: Option<&ProgressBar>,
+) -> anyhow::Result<()> {
+    if parallel <= 1 {
+        let mut stream = client
+            .stream(file.hash.clone(), 0, file.size)
             .await?
             .res()?;
+        return copy(&mut stream, writer, progress).await;
+    }
 
-        written += n;
+    let mut offset = 0;
+    let mut pending = Transfers::new();
+    loop {
+        while offset < file.size && pending.len() < parallel {
+            let len = DOWNLOAD_RANGE_SIZE.min(file.size - offset);
+            let (client, hash) = (client.clone(), file.hash.clone());
+
+            pending.push_back(tokio::spawn(async move {
+                Ok(client.download(hash, offset, len).await?.res()?)
+            }));
+            offset += len;
+        }
+
+        let Some(handle) = pending.pop_front() else {
+            break;
+        };
+
+        let data = join(handle, &mut pending).await?;
+        writer.write_all(&data).await?;
+        if let Some(progress) = progress {
+            progress.inc(data.len() as u64);
+        }
     }
 
-    progress.finish();
+    Ok(())
+}
 
-    let file = client
-        .commit_blob(blob.name, name, tags, replace)
-        .await?
-        .res()?;
+async fn join<T>(
+    handle: JoinHandle<anyhow::Result<T>>,
+    pending: &mut Transfers<T>,
+) -> anyhow::Result<T> {
+    let rsp = match handle.await {
+        Ok(rsp) => rsp,
+        Err(e) => Err(e.into()),
+    };
+
+    if rsp.is_err() {
+        for handle in pending.drain(..) {
+            handle.abort();
+        }
+    }
+
+    rsp
+}
+
+async fn hash_prefix(
+    file: &mut tokio::fs::File,
+    len: u64,
+    hasher: &mut Hasher,
+    buf: &mut [u8],
+) -> anyhow::Result<()> {
+    let mut hashed = 0;
+    while hashed < len {
+        let n = buf.len().min((len - hashed) as usize);
+        file.read_exact(&mut buf[0..n]).await?;
+        hasher.update(&buf[0..n]);
+        hashed += n as u64;
+    }
 
-    println!("{}", display_file(&file));
     Ok(())
 }
 
-async fn download(client: Client, path: PathBuf, name: String) -> anyhow::Result<()> {
-    let remote_file = client.describe(name).await?.res()?;
+async fn hash_file(file: &mut tokio::fs::File) -> anyhow::Result<ContentHash> {
+    let mut hasher = Hasher::default();
+    let mut buf = vec![0; STREAM_BUFFER_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buf[0..n]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+async fn describe(
+    client: &Client,
+    name: String,
+    version: Option<u64>,
+) -> anyhow::Result<FileDescription> {
+    let Some(version) = version else {
+        return Ok(client.describe(name).await?.res()?);
+    };
+
+    let file = client
+        .versions(name.clone())
+        .await?
+        .res()?
+        .into_iter()
+        .find(|v| v.version == version)
+        .ok_or_else(|| anyhow::anyhow!("No version {version} of {name}"))?;
+
+    Ok(FileDescription {
+        name,
+        size: file.size,
+        hash: file.hash,
+        created: file.created,
+        tags: file.tags,
+    })
+}
+
+async fn download(
+    client: Client,
+    path: PathBuf,
+    name: String,
+    version: Option<u64>,
+    parallel: usize,
+) -> anyhow::Result<()> {
+    let remote_file = describe(&client, name, version).await?;
 
     let temp_path = format!("{}.stashdl", path.display());
     let mut local_file = tokio::fs::File::create(&temp_path).await?;
 
     let progress = progress_bar(remote_file.size);
-
-    let mut cursor = 0;
-    while cursor < remote_file.size {
-        progress.set_position(cursor);
-        let len = std::cmp::min(CHUNK_SIZE as u64, remote_file.size - cursor);
-        let chunk = client
-            .download(remote_file.hash.clone(), cursor, len)
-            .await?
-            .res()?;
-        local_file.write_all(&chunk).await?;
-        cursor += len;
-    }
+    fetch(
+        &client,
+        &remote_file,
+        parallel,
+        &mut local_file,
+        Some(&progress),
+    )
+    .await?;
 
     local_file.flush().await?;
     tokio::fs::rename(temp_path, path).await?;
@@ -137,40 +554,254 @@ async fn download(client: Client, path: PathBuf, name: String) -> anyhow::Result
     Ok(())
 }
 
-async fn read(client: Client, name: String) -> anyhow::Result<()> {
-    let remote_file = client.describe(name).await?.res()?;
+async fn read(
+    client: Client,
+    name: String,
+    version: Option<u64>,
+    parallel: usize,
+) -> anyhow::Result<()> {
+    let remote_file = describe(&client, name, version).await?;
 
     let mut stdout = tokio::io::stdout();
+    fetch(&client, &remote_file, parallel, &mut stdout, None).await?;
+    stdout.flush().await?;
 
-    let mut cursor = 0;
-    while cursor < remote_file.size {
-        let len = std::cmp::min(CHUNK_SIZE as u64, remote_file.size - cursor);
-        let chunk = client
-            .download(remote_file.hash.clone(), cursor, len)
-            .await?
-            .res()?;
+    Ok(())
+}
+
+async fn delete(client: Client, name: String) -> anyhow::Result<()> {
+    let rsp = client.delete(name).await?.res()?;
+
+    println!("{rsp}");
+    Ok(())
+}
+
+async fn mv(client: Client, from: String, to: String, replace: bool) -> anyhow::Result<()> {
+    let file = client.rename(from, to, replace).await?.res()?;
 
-        stdout.write(&chunk).await?;
+    println!("{}", display_file(&file));
+    Ok(())
+}
 
-        cursor += len;
+async fn cp(
+    client: Client,
+    from: String,
+    to: String,
+    tags: Option<Vec<String>>,
+) -> anyhow::Result<()> {
+    let tags = tags
+        .map(|tags| {
+            tags.iter()
+                .map(|t| parse_tag(t))
+                .collect::<anyhow::Result<Vec<Tag>>>()
+        })
+        .transpose()?;
+
+    let file = client.copy(from, to, tags).await?.res()?;
+
+    println!("{}", display_file(&file));
+    Ok(())
+}
+
+async fn retag(
+    client: Client,
+    names: Vec<String>,
+    add: Vec<String>,
+    remove: Vec<String>,
+    search: Option<Vec<String>>,
+) -> anyhow::Result<()> {
+    let parse = |tags: Vec<String>| {
+        tags.iter()
+            .map(|t| parse_tag(t))
+            .collect::<anyhow::Result<Vec<Tag>>>()
+    };
+    let (add, remove) = (parse(add)?, parse(remove)?);
+
+    let files = match search.as_deref() {
+        Some([tag, term]) => FileSelection::Search {
+            tag: parse_tag(tag)?.into(),
+            term: term.clone(),
+        },
+        _ => FileSelection::Names(names),
+    };
+
+    let files = client.edit_tags(files, add, remove).await?.res()?;
+    for file in files.iter() {
+        println!("{}\t{}", file.name, file.tags.join(","));
     }
 
-    stdout.flush().await?;
+    Ok(())
+}
+
+async fn trash(client: Client) -> anyhow::Result<()> {
+    let entries = client.trash().await?.res()?;
+    for entry in entries.iter() {
+        println!(
+            "{} {} {} {} {} {} versions\t{}",
+            entry.id,
+            entry.deleted,
+            entry.deleted_by,
+            entry.hash,
+            entry.size,
+            entry.versions,
+            entry.name
+        );
+    }
 
     Ok(())
 }
 
-async fn delete(client: Client, name: String) -> anyhow::Result<()> {
-    let rsp = client.delete(name).await?.res()?;
+async fn undelete(client: Client, id: u64, name: Option<String>) -> anyhow::Result<()> {
+    let file = client.restore_trash(id, name).await?.res()?;
+
+    println!("{}", display_file(&file));
+    Ok(())
+}
+
+async fn purge(client: Client, id: Option<u64>, all: bool) -> anyhow::Result<()> {
+    if id.is_none() && !all {
+        return Err(anyhow::anyhow!("Pass a trash entry id or --all"));
+    }
+
+    let purged = client.purge_trash(id).await?.res()?;
+
+    println!("Purged {purged} entries");
+    Ok(())
+}
+
+async fn versions(client: Client, name: String) -> anyhow::Result<()> {
+    let versions = client.versions(name).await?.res()?;
+    for version in versions.iter() {
+        println!(
+            "{}{} {} {} {} {}\t{}",
+            if version.current { "*" } else { " " },
+            version.version,
+            version.created,
+            version.hash,
+            version.size,
+            version.uploader,
+            version.tags.join(",")
+        );
+    }
+
+    Ok(())
+}
+
+async fn restore(client: Client, name: String, version: u64) -> anyhow::Result<()> {
+    let file = client.restore_version(name, version).await?.res()?;
+
+    println!("{}", display_file(&file));
+    Ok(())
+}
+
+async fn keep_versions(client: Client, name: String, keep: Option<u32>) -> anyhow::Result<()> {
+    let rsp = client.set_version_limit(name, keep).await?.res()?;
 
     println!("{rsp}");
     Ok(())
 }
 
+async fn blobs(client: Client) -> anyhow::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+    let blobs = client.list_blobs().await?.res()?;
+    for blob in blobs.iter() {
+        println!(
+            "{} {} {}\t{}s\t{}s idle",
+            blob.name,
+            blob.owner,
+            blob.size,
+            now - blob.created,
+            now - blob.updated
+        );
+    }
+
+    Ok(())
+}
+
 async fn gc_blobs(client: Client) -> anyhow::Result<()> {
-    let rsp = client.gc_blobs().await?.res()?;
+    let report = client.gc_blobs().await?.res()?;
+    for name in report.removed.iter() {
+        println!("removed {name}");
+    }
+
+    println!(
+        "Removed {} blobs, freed {} bytes",
+        report.removed.len(),
+        report.freed
+    );
+    Ok(())
+}
+
+async fn stats(client: Client) -> anyhow::Result<()> {
+    let stats = client.stats().await?.res()?;
+    println!("files:        {}", stats.files);
+    println!("contents:     {}", stats.contents);
+    println!("chunks:       {}", stats.chunks);
+    println!("logical size: {}", stats.logical_size);
+    println!("content size: {}", stats.content_size);
+    println!("stored size:  {}", stats.stored_size);
+
+    if stats.stored_size > 0 {
+        println!(
+            "dedup ratio:  {:.2}",
+            stats.logical_size as f64 / stats.stored_size as f64
+        );
+    }
 
-    println!("{rsp}");
+    Ok(())
+}
+
+async fn rotate_keys(client: Client) -> anyhow::Result<()> {
+    let report = client.rotate_keys().await?.res()?;
+    println!("Rewrapped {} data keys", report.rewrapped);
+    Ok(())
+}
+
+async fn scrub(
+    client: Client,
+    quarantine: bool,
+    batch: u32,
+    after: Option<String>,
+) -> anyhow::Result<()> {
+    let mut after = after
+        .map(|h| ContentHash::from_str(&h).map_err(|_| anyhow::anyhow!("Invalid hash {h}")))
+        .transpose()?;
+
+    let (mut checked, mut problems) = (0, 0);
+    loop {
+        let report = client.scrub(after, batch, quarantine).await?.res()?;
+
+        for hash in report.corrupted.iter() {
+            println!("corrupted {hash}");
+        }
+        for hash in report.missing.iter() {
+            println!("missing {hash}");
+        }
+        for name in report.orphaned.iter() {
+            println!("orphaned {name}");
+        }
+        for hash in report.corrupted_chunks.iter() {
+            println!("corrupted chunk {hash}");
+        }
+        for name in report.orphaned_chunks.iter() {
+            println!("orphaned chunk {name}");
+        }
+
+        checked += report.checked;
+        problems += report.corrupted.len()
+            + report.missing.len()
+            + report.orphaned.len()
+            + report.corrupted_chunks.len()
+            + report.orphaned_chunks.len();
+
+        match report.next {
+            Some(next) => after = Some(next),
+            None => break,
+        }
+    }
+
+    println!("Checked {checked} objects, {problems} problems found");
     Ok(())
 }
 
@@ -196,6 +827,27 @@ async fn search(client: Client, tag: String, term: String) -> anyhow::Result<()>
     Ok(())
 }
 
+async fn copy<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    reader: &mut R,
+    writer: &mut W,
+    progress: Option<&ProgressBar>,
+) -> anyhow::Result<()> {
+    let mut buf = vec![0; STREAM_BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+
+        writer.write_all(&buf[0..n]).await?;
+        if let Some(progress) = progress {
+            progress.inc(n as u64);
+        }
+    }
+
+    Ok(())
+}
+
 fn parse_tag(tag: &str) -> anyhow::Result<Tag> {
     Tag::from_str(tag).map_err(|_| anyhow::anyhow!("Invalid tag {tag}"))
 }
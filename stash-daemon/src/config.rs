@@ -1,7 +1,9 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
+use anyhow::Context;
 use envconfig::Envconfig;
 use iroh::SecretKey;
+use stash::{CompressionPolicy, Limits, MasterKey, S3Config};
 
 #[derive(Clone, Debug, Envconfig)]
 pub struct Config {
@@ -13,10 +15,169 @@ pub struct Config {
 
     #[envconfig(from = "STASH_SECRET_KEY")]
     pub secret_key: SecretKey,
+
+    #[envconfig(from = "STASH_MAX_REQUEST_SIZE")]
+    pub max_request_size: Option<usize>,
+
+    #[envconfig(from = "STASH_MAX_DOWNLOAD_LEN")]
+    pub max_download_len: Option<u64>,
+
+    #[envconfig(from = "STASH_MAX_APPEND_SIZE")]
+    pub max_append_size: Option<u64>,
+
+    #[envconfig(from = "STASH_MAX_BLOB_SIZE")]
+    pub max_blob_size: Option<u64>,
+
+    #[envconfig(from = "STASH_MAX_CONCURRENT_REQUESTS")]
+    pub max_concurrent_requests: Option<usize>,
+
+    #[envconfig(from = "STASH_BLOB_TTL_SECS", default = "86400")]
+    pub blob_ttl_secs: u64,
+
+    #[envconfig(from = "STASH_BLOB_GC_INTERVAL_SECS", default = "3600")]
+    pub blob_gc_interval_secs: u64,
+
+    #[envconfig(from = "STASH_TRASH_RETENTION_SECS", default = "2592000")]
+    pub trash_retention_secs: u64,
+
+    #[envconfig(from = "STASH_TRASH_EXPIRY_INTERVAL_SECS", default = "3600")]
+    pub trash_expiry_interval_secs: u64,
+
+    #[envconfig(from = "STASH_MAX_VERSIONS", default = "10")]
+    pub max_versions: u32,
+
+    #[envconfig(from = "STASH_CHUNKING", default = "false")]
+    pub chunking: bool,
+
+    #[envconfig(from = "STASH_COMPRESSION", default = "false")]
+    pub compression: bool,
+
+    #[envconfig(from = "STASH_COMPRESSION_LEVEL")]
+    pub compression_level: Option<i32>,
+
+    #[envconfig(from = "STASH_COMPRESSION_MIN_SIZE")]
+    pub compression_min_size: Option<u64>,
+
+    #[envconfig(from = "STASH_COMPRESSION_TAGS", default = "")]
+    pub compression_tags: String,
+
+    #[envconfig(from = "STASH_S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+
+    #[envconfig(from = "STASH_S3_BUCKET")]
+    pub s3_bucket: Option<String>,
+
+    #[envconfig(from = "STASH_S3_REGION", default = "us-east-1")]
+    pub s3_region: String,
+
+    #[envconfig(from = "STASH_S3_ACCESS_KEY")]
+    pub s3_access_key: Option<String>,
+
+    #[envconfig(from = "STASH_S3_SECRET_KEY")]
+    pub s3_secret_key: Option<String>,
+
+    #[envconfig(from = "STASH_S3_PREFIX", default = "")]
+    pub s3_prefix: String,
+
+    #[envconfig(from = "STASH_MASTER_KEY")]
+    pub master_key: Option<String>,
+
+    #[envconfig(from = "STASH_PREVIOUS_MASTER_KEYS", default = "")]
+    pub previous_master_keys: String,
 }
 
 impl Config {
     pub fn build() -> Self {
         Self::init_from_env().unwrap()
     }
+
+    pub fn limits(&self) -> Limits {
+        let defaults = Limits::default();
+
+        Limits {
+            max_request_size: self.max_request_size.unwrap_or(defaults.max_request_size),
+            max_download_len: self.max_download_len.unwrap_or(defaults.max_download_len),
+            max_append_size: self.max_append_size.unwrap_or(defaults.max_append_size),
+            max_blob_size: self.max_blob_size.unwrap_or(defaults.max_blob_size),
+            max_concurrent_requests: self
+                .max_concurrent_requests
+                .unwrap_or(defaults.max_concurrent_requests),
+        }
+    }
+
+    pub fn blob_ttl(&self) -> Duration {
+        Duration::from_secs(self.blob_ttl_secs)
+    }
+
+    pub fn blob_gc_interval(&self) -> Duration {
+        Duration::from_secs(self.blob_gc_interval_secs)
+    }
+
+    pub fn compression(&self) -> Option<CompressionPolicy> {
+        if !self.compression {
+            return None;
+        }
+
+        let defaults = CompressionPolicy::default();
+
+        Some(CompressionPolicy {
+            level: self.compression_level.unwrap_or(defaults.level),
+            min_size: self.compression_min_size.unwrap_or(defaults.min_size),
+            tags: self
+                .compression_tags
+                .split(',')
+                .map(|t| t.trim())
+                .filter(|t| !t.is_empty())
+                .map(String::from)
+                .collect(),
+        })
+    }
+
+    pub fn trash_retention(&self) -> Duration {
+        Duration::from_secs(self.trash_retention_secs)
+    }
+
+    pub fn trash_expiry_interval(&self) -> Duration {
+        Duration::from_secs(self.trash_expiry_interval_secs)
+    }
+
+    pub fn s3(&self) -> anyhow::Result<Option<S3Config>> {
+        let Some(endpoint) = self.s3_endpoint.clone() else {
+            return Ok(None);
+        };
+
+        Ok(Some(S3Config {
+            endpoint,
+            bucket: self
+                .s3_bucket
+                .clone()
+                .context("STASH_S3_BUCKET is not set")?,
+            region: self.s3_region.clone(),
+            access_key: self
+                .s3_access_key
+                .clone()
+                .context("STASH_S3_ACCESS_KEY is not set")?,
+            secret_key: self
+                .s3_secret_key
+                .clone()
+                .context("STASH_S3_SECRET_KEY is not set")?,
+            prefix: self.s3_prefix.clone(),
+        }))
+    }
+
+    pub fn master_keys(&self) -> anyhow::Result<Option<(MasterKey, Vec<MasterKey>)>> {
+        let Some(master_key) = self.master_key.as_deref() else {
+            return Ok(None);
+        };
+
+        let master_key = master_key.parse().context("Invalid STASH_MASTER_KEY")?;
+        let previous = self
+            .previous_master_keys
+            .split(',')
+            .filter(|k| !k.trim().is_empty())
+            .map(|k| k.parse().context("Invalid STASH_PREVIOUS_MASTER_KEYS"))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Some((master_key, previous)))
+    }
 }
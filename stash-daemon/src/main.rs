@@ -1,20 +1,76 @@
 mod config;
 
+use std::time::Duration;
+
 use config::Config;
 use iroh::{Endpoint, NodeId, protocol::Router};
-use stash::Server;
+use stash::{EncryptedStorage, LocalStorage, S3Storage, Server, Storage};
 use tokio::signal::unix::{SignalKind, signal};
 
-const GATEKEEPER_ROLE: &'static str = "stash";
+const GATEKEEPER_ROLE: &str = "stash";
+const GATEKEEPER_ADMIN_ROLE: &str = "stash-admin";
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().unwrap();
     let config = Config::build();
 
-    let gk = gatekeeper::Arbiter::new(config.gatekeeper_db_path, true).await?;
-    let gk_server = gatekeeper::Server::new(gk.clone());
-    let stash_server = Server::new(Auth { gk }, config.root).await?;
+    let gk = gatekeeper::Arbiter::new(config.gatekeeper_db_path.clone(), true).await?;
+
+    let local = LocalStorage::open(config.root.canonicalize()?).await?;
+    match config.s3()? {
+        Some(s3) => encrypt(config, gk, S3Storage::new(s3, local)?).await,
+        None => encrypt(config, gk, local).await,
+    }
+}
+
+async fn encrypt<S: Storage>(
+    config: Config,
+    gk: gatekeeper::Arbiter,
+    storage: S,
+) -> anyhow::Result<()> {
+    match config.master_keys()? {
+        Some((master, previous)) => {
+            let storage = EncryptedStorage::open(storage, master, previous).await?;
+            run(config, gk, storage).await
+        }
+        None => {
+            if EncryptedStorage::is_encrypted(&storage).await? {
+                anyhow::bail!("Storage is encrypted, but STASH_MASTER_KEY is not set");
+            }
+
+            run(config, gk, storage).await
+        }
+    }
+}
+
+async fn run<S: Storage>(
+    config: Config,
+    gk: gatekeeper::Arbiter,
+    storage: S,
+) -> anyhow::Result<()> {
+    let limits = config.limits();
+    let blob_ttl = config.blob_ttl();
+    let blob_gc_interval = config.blob_gc_interval();
+    let trash_expiry_interval = config.trash_expiry_interval();
+
+    let auth = Auth { gk: gk.clone() };
+    let gk_server = gatekeeper::Server::new(gk);
+    let mut stash_server = Server::with_storage(auth, config.root.clone(), storage)
+        .await?
+        .with_limits(limits)
+        .with_blob_ttl(blob_ttl)
+        .with_trash_retention(config.trash_retention())
+        .with_chunking(config.chunking)
+        .with_max_versions(config.max_versions);
+    if let Some(policy) = config.compression() {
+        stash_server = stash_server.with_compression(policy);
+    }
+
+    let gc = (!blob_gc_interval.is_zero())
+        .then(|| tokio::spawn(gc(stash_server.clone(), blob_gc_interval)));
+    let expiry = (!trash_expiry_interval.is_zero())
+        .then(|| tokio::spawn(expire_trash(stash_server.clone(), trash_expiry_interval)));
 
     let endpoint = Endpoint::builder()
         .discovery_n0()
@@ -30,11 +86,37 @@ async fn main() -> anyhow::Result<()> {
     let mut sigterm = signal(SignalKind::terminate())?;
     sigterm.recv().await;
 
+    if let Some(gc) = gc {
+        gc.abort();
+    }
+    if let Some(expiry) = expiry {
+        expiry.abort();
+    }
     router.shutdown().await?;
 
     Ok(())
 }
 
+async fn gc<S: Storage>(server: Server<Auth, S>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        if let Err(e) = server.gc_blobs().await {
+            tracing::error!(err = ?e, "gc_blobs_failed");
+        }
+    }
+}
+
+async fn expire_trash<S: Storage>(server: Server<Auth, S>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        if let Err(e) = server.expire_trash().await {
+            tracing::error!(err = ?e, "expire_trash_failed");
+        }
+    }
+}
+
 struct Auth {
     gk: gatekeeper::Arbiter,
 }
@@ -49,4 +131,14 @@ impl stash::NodeAuth for Auth {
             Ok(roles) => roles.iter().any(|r| r == GATEKEEPER_ROLE),
         }
     }
+
+    async fn admin(&self, node: NodeId) -> bool {
+        match self.gk.node_roles(&format!("{node}")).await {
+            Err(e) => {
+                tracing::error!(err = ?e, "node_admin_auth_failed");
+                false
+            }
+            Ok(roles) => roles.iter().any(|r| r == GATEKEEPER_ADMIN_ROLE),
+        }
+    }
 }
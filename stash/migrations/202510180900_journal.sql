@@ -0,0 +1,7 @@
+CREATE TABLE journal (
+    id INTEGER PRIMARY KEY,
+    op TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    blob TEXT,
+    created TEXT NOT NULL
+);
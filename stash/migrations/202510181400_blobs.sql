@@ -0,0 +1,9 @@
+CREATE TABLE blobs (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    created TEXT NOT NULL,
+    updated TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX ix_blobs_name ON blobs(name);
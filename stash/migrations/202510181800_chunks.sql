@@ -0,0 +1,22 @@
+ALTER TABLE file_contents ADD COLUMN chunked INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE chunks (
+    id INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    created TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX ix_chunks_hash ON chunks(hash);
+
+CREATE TABLE content_chunks (
+    id INTEGER PRIMARY KEY,
+    content_id INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    chunk_id INTEGER NOT NULL,
+    FOREIGN KEY (content_id) REFERENCES file_contents(id) ON DELETE CASCADE,
+    FOREIGN KEY (chunk_id) REFERENCES chunks(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX ix_content_chunks_content_seq ON content_chunks(content_id, seq);
+CREATE INDEX ix_content_chunks_chunk ON content_chunks(chunk_id);
@@ -0,0 +1,12 @@
+ALTER TABLE file_contents ADD COLUMN compressed INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE content_frames (
+    id INTEGER PRIMARY KEY,
+    content_id INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    raw_size INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    FOREIGN KEY (content_id) REFERENCES file_contents(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX ix_content_frames_content_seq ON content_frames(content_id, seq);
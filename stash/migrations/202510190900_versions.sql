@@ -0,0 +1,14 @@
+ALTER TABLE files ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE files ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
+
+DROP INDEX ix_files_name;
+CREATE UNIQUE INDEX ix_files_name_version ON files(name, version);
+CREATE UNIQUE INDEX ix_files_current_name ON files(name) WHERE archived = 0;
+
+CREATE TABLE version_limits (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    keep INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX ix_version_limits_name ON version_limits(name);
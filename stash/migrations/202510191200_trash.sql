@@ -0,0 +1,15 @@
+CREATE TABLE trash (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    deleted_by TEXT NOT NULL,
+    deleted TEXT NOT NULL,
+    keep INTEGER
+);
+
+ALTER TABLE files ADD COLUMN trash_id INTEGER REFERENCES trash(id) ON DELETE CASCADE;
+
+DROP INDEX ix_files_name_version;
+DROP INDEX ix_files_current_name;
+CREATE UNIQUE INDEX ix_files_name_version ON files(name, version) WHERE trash_id IS NULL;
+CREATE UNIQUE INDEX ix_files_current_name ON files(name) WHERE archived = 0 AND trash_id IS NULL;
+CREATE INDEX ix_files_trash ON files(trash_id);
@@ -0,0 +1 @@
+DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM file_tags);
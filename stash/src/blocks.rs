@@ -0,0 +1,105 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Reads `inner` in blocks of the given sizes and yields each block passed
+/// through `transform`. The input may end early; the last block is then
+/// transformed as far as it was read.
+pub struct Blocks<R, I, F> {
+    inner: R,
+    sizes: I,
+    transform: F,
+    buf: Vec<u8>,
+    filled: usize,
+    out: Vec<u8>,
+    pos: usize,
+    skip: usize,
+    eof: bool,
+}
+
+impl<R, I, F> Blocks<R, I, F>
+where
+    R: AsyncRead + Unpin,
+    I: Iterator<Item = usize> + Unpin,
+    F: FnMut(&[u8]) -> io::Result<Vec<u8>> + Unpin,
+{
+    pub fn new(inner: R, sizes: I, transform: F) -> Self {
+        Self {
+            inner,
+            sizes,
+            transform,
+            buf: vec![],
+            filled: 0,
+            out: vec![],
+            pos: 0,
+            skip: 0,
+            eof: false,
+        }
+    }
+
+    /// Emits `prefix` before the first block.
+    pub fn with_prefix(mut self, prefix: Vec<u8>) -> Self {
+        self.out = prefix;
+        self
+    }
+
+    /// Drops the first `skip` bytes of transformed output.
+    pub fn with_skip(mut self, skip: usize) -> Self {
+        self.skip = skip;
+        self
+    }
+}
+
+impl<R, I, F> AsyncRead for Blocks<R, I, F>
+where
+    R: AsyncRead + Unpin,
+    I: Iterator<Item = usize> + Unpin,
+    F: FnMut(&[u8]) -> io::Result<Vec<u8>> + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.pos < this.out.len() {
+                let n = buf.remaining().min(this.out.len() - this.pos);
+                buf.put_slice(&this.out[this.pos..this.pos + n]);
+                this.pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.filled == 0 && this.buf.is_empty() {
+                match this.sizes.next() {
+                    Some(size) if !this.eof => this.buf.resize(size, 0),
+                    _ => return Poll::Ready(Ok(())),
+                }
+            }
+
+            while !this.eof && this.filled < this.buf.len() {
+                let mut read = ReadBuf::new(&mut this.buf[this.filled..]);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+
+                match read.filled().len() {
+                    0 => this.eof = true,
+                    n => this.filled += n,
+                }
+            }
+
+            let filled = std::mem::take(&mut this.filled);
+            let block = std::mem::take(&mut this.buf);
+            if filled == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            this.out = (this.transform)(&block[..filled])?;
+            this.pos = this.skip.min(this.out.len());
+            this.skip -= this.pos;
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+use crate::{
+    ContentHash, Error, sha256,
+    storage::{ObjectReader, Storage},
+};
+
+const MIN_SIZE: usize = 256 * 1024;
+const AVG_SIZE: usize = 1024 * 1024;
+const MAX_SIZE: usize = 4 * 1024 * 1024;
+
+const GEAR: [u64; 256] = gear();
+
+const fn gear() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+
+    table
+}
+
+pub struct Chunker {
+    min: usize,
+    avg: usize,
+    max: usize,
+    mask_s: u64,
+    mask_l: u64,
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new(MIN_SIZE, AVG_SIZE, MAX_SIZE)
+    }
+}
+
+impl Chunker {
+    pub fn new(min: usize, avg: usize, max: usize) -> Self {
+        let bits = avg.ilog2();
+
+        Self {
+            min,
+            avg,
+            max,
+            mask_s: !0 << (64 - (bits + 1)),
+            mask_l: !0 << (64 - (bits - 1)),
+        }
+    }
+
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min {
+            return data.len();
+        }
+
+        let end = data.len().min(self.max);
+        let normal = end.min(self.avg);
+
+        let mut hash: u64 = 0;
+        for (i, b) in data.iter().enumerate().take(end).skip(self.min) {
+            hash = (hash << 1).wrapping_add(GEAR[*b as usize]);
+
+            let mask = if i < normal { self.mask_s } else { self.mask_l };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+
+        end
+    }
+
+    pub fn split<R: AsyncRead + Unpin>(&self, reader: R) -> Chunks<'_, R> {
+        Chunks {
+            chunker: self,
+            reader,
+            buf: Vec::with_capacity(self.max * 2),
+            eof: false,
+        }
+    }
+}
+
+pub struct Chunks<'a, R> {
+    chunker: &'a Chunker,
+    reader: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> Chunks<'_, R> {
+    pub async fn next(&mut self) -> Result<Option<(Vec<u8>, ContentHash)>, Error> {
+        let max = self.chunker.max;
+        while !self.eof && self.buf.len() < max {
+            let len = self.buf.len();
+            self.buf.resize(len + max, 0);
+
+            let n = self.reader.read(&mut self.buf[len..]).await?;
+            self.buf.truncate(len + n);
+            self.eof = n == 0;
+        }
+
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+
+        let cut = self.chunker.cut(&self.buf);
+        let chunk: Vec<u8> = self.buf.drain(0..cut).collect();
+
+        let mut hasher = sha256::Hasher::default();
+        hasher.update(&chunk);
+
+        Ok(Some((chunk, hasher.finalize())))
+    }
+}
+
+type Open = Pin<Box<dyn Future<Output = Result<ObjectReader, Error>> + Send>>;
+
+pub struct ChunkReader<S> {
+    storage: Arc<S>,
+    pending: VecDeque<(String, u64, u64)>,
+    opening: Option<Open>,
+    current: Option<ObjectReader>,
+}
+
+impl<S: Storage> ChunkReader<S> {
+    pub fn new(storage: Arc<S>, chunks: Vec<(String, u64)>, start: u64, len: u64) -> Self {
+        let mut pending = VecDeque::new();
+        let mut offset = 0;
+        let end = start + len;
+
+        for (key, size) in chunks {
+            let chunk_start = offset;
+            let chunk_end = offset + size;
+            offset = chunk_end;
+
+            if chunk_end <= start || chunk_start >= end {
+                continue;
+            }
+
+            let skip = start.saturating_sub(chunk_start);
+            let take = chunk_end.min(end) - chunk_start - skip;
+            pending.push_back((key, skip, take));
+        }
+
+        Self {
+            storage,
+            pending,
+            opening: None,
+            current: None,
+        }
+    }
+}
+
+impl<S: Storage> AsyncRead for ChunkReader<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        loop {
+            if let Some(current) = self.current.as_mut() {
+                let filled = buf.filled().len();
+                match Pin::new(current).poll_read(cx, buf) {
+                    Poll::Ready(Ok(())) if buf.filled().len() == filled => self.current = None,
+                    rsp => return rsp,
+                }
+            }
+
+            if let Some(opening) = self.opening.as_mut() {
+                match opening.as_mut().poll(cx) {
+                    Poll::Ready(Ok(file)) => {
+                        self.opening = None;
+                        self.current = Some(file);
+                        continue;
+                    }
+                    Poll::Ready(Err(e)) => {
+                        self.opening = None;
+                        return Poll::Ready(Err(match e {
+                            Error::IoError(e) => e,
+                            e => std::io::Error::other(e.to_string()),
+                        }));
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            match self.pending.pop_front() {
+                None => return Poll::Ready(Ok(())),
+                Some((key, skip, take)) => {
+                    let storage = self.storage.clone();
+                    self.opening = Some(Box::pin(async move {
+                        storage.get_range(&key, skip, take).await
+                    }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chunker;
+
+    fn data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    async fn chunks(chunker: &Chunker, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut split = chunker.split(data);
+        let mut chunks = vec![];
+        while let Some((chunk, _)) = split.next().await.unwrap() {
+            chunks.push(chunk);
+        }
+
+        chunks
+    }
+
+    #[tokio::test]
+    async fn chunk_bounds() {
+        let chunker = Chunker::new(1024, 4096, 16384);
+        let data = data(1_000_000, 1);
+
+        let chunks = chunks(&chunker, &data).await;
+        assert_eq!(chunks.concat(), data);
+
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(last.len() <= 16384);
+        for chunk in rest {
+            assert!(chunk.len() >= 1024 && chunk.len() <= 16384);
+        }
+
+        let avg = data.len() / chunks.len();
+        assert!(avg > 2048 && avg < 8192);
+    }
+
+    #[tokio::test]
+    async fn chunk_stability() {
+        let chunker = Chunker::new(1024, 4096, 16384);
+        let original = data(1_000_000, 2);
+
+        let mut edited = original.clone();
+        edited.splice(500_000..500_010, [0u8; 100]);
+
+        let a = chunks(&chunker, &original).await;
+        let b = chunks(&chunker, &edited).await;
+
+        let shared = b.iter().filter(|c| a.contains(c)).count();
+        assert!(shared + 4 >= b.len());
+    }
+}
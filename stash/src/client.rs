@@ -1,22 +1,47 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
 use bincode::Decode;
-use iroh::{Endpoint, NodeAddr, NodeId};
+use iroh::{
+    Endpoint, NodeAddr, NodeId,
+    endpoint::{Connection, RecvStream, SendStream, WriteError},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, ReadBuf},
+    sync::Mutex,
+};
 
-use crate::{ALPN, Blob, Cmd, Error, File, FileDescription, Response, SHA256, Tag, common::Either};
+use crate::{
+    ALPN, Blob, BlobId, BlobInfo, Cmd, ContentHash, Error, File, FileDescription, FileSelection,
+    FileVersion, GcReport, Hello, PROTOCOL_VERSION, Response, RotationReport, ScrubReport,
+    ServerInfo, Stats, Tag, TagInfo, TrashEntry, common::Either, frame,
+};
 
 const CHUNK_SIZE: usize = 1_000_000;
+const MAX_HEADER_SIZE: usize = 1_000_000;
 
 #[derive(Clone)]
 pub struct Client {
     endpoint: Endpoint,
     server: Either<NodeAddr, NodeId>,
+    session: Arc<Mutex<Option<Session>>>,
     bincode_config: bincode::config::Configuration,
 }
 
+struct Session {
+    conn: Connection,
+    info: ServerInfo,
+}
+
 impl Client {
     pub fn new(endpoint: Endpoint, server: NodeId) -> Self {
         Self {
             endpoint,
             server: Either::Right(server),
+            session: Arc::new(Mutex::new(None)),
             bincode_config: bincode::config::standard(),
         }
     }
@@ -25,10 +50,16 @@ impl Client {
         Self {
             endpoint,
             server: Either::Left(server),
+            session: Arc::new(Mutex::new(None)),
             bincode_config: bincode::config::standard(),
         }
     }
 
+    pub async fn server_info(&self) -> Result<ServerInfo, Error> {
+        let (_, info) = self.session().await?;
+        Ok(info)
+    }
+
     pub async fn tags(&self) -> Result<Response<Vec<String>>, Error> {
         self.send(Cmd::Tags).await
     }
@@ -37,20 +68,37 @@ impl Client {
         self.send(Cmd::CreateBlob).await
     }
 
-    pub async fn describe_blob(&self, name: String) -> Result<Response<Blob>, Error> {
+    pub async fn describe_blob(&self, name: BlobId) -> Result<Response<Blob>, Error> {
         self.send(Cmd::DescribeBlob { name }).await
     }
 
-    pub async fn append_blob(&self, name: String, data: Vec<u8>) -> Result<Response<Blob>, Error> {
-        self.send(Cmd::AppendBlob { name, data }).await
+    pub async fn append_blob(
+        &self,
+        name: BlobId,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Response<Blob>, Error> {
+        self.send(Cmd::AppendBlob { name, offset, data }).await
+    }
+
+    pub async fn write_blob(
+        &self,
+        name: BlobId,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Response<Blob>, Error> {
+        self.require("write-blob").await?;
+        self.send(Cmd::WriteBlob { name, offset, data }).await
     }
 
     pub async fn commit_blob(
         &self,
-        name: String,
+        name: BlobId,
         file_name: String,
         tags: Vec<Tag>,
         replace: bool,
+        hash: Option<ContentHash>,
+        size: Option<u64>,
     ) -> Result<Response<File>, Error> {
         let tags = tags.into_iter().map(Into::into).collect();
 
@@ -59,11 +107,18 @@ impl Client {
             file_name,
             tags,
             replace,
+            hash,
+            size,
         })
         .await
     }
 
-    pub async fn gc_blobs(&self) -> Result<Response<String>, Error> {
+    pub async fn list_blobs(&self) -> Result<Response<Vec<BlobInfo>>, Error> {
+        self.require("list-blobs").await?;
+        self.send(Cmd::ListBlobs).await
+    }
+
+    pub async fn gc_blobs(&self) -> Result<Response<GcReport>, Error> {
         self.send(Cmd::GcBlobs).await
     }
 
@@ -97,33 +152,383 @@ impl Client {
 
     pub async fn download(
         &self,
-        hash: SHA256,
+        hash: ContentHash,
         start: u64,
         len: u64,
     ) -> Result<Response<Vec<u8>>, Error> {
         self.send(Cmd::Download { hash, start, len }).await
     }
 
-    async fn send<R: Decode<()>>(&self, cmd: Cmd) -> Result<R, Error> {
-        let json = bincode::encode_to_vec(&cmd, self.bincode_config)?;
+    pub async fn stream(
+        &self,
+        hash: ContentHash,
+        start: u64,
+        len: u64,
+    ) -> Result<Response<Download>, Error> {
+        self.require("stream").await?;
+
+        let (mut tx, mut rx) = self.open().await?;
+        frame::write(
+            &mut tx,
+            &Cmd::Stream { hash, start, len },
+            self.bincode_config,
+        )
+        .await?;
+        tx.finish()?;
+
+        let rsp = match frame::read(&mut rx, self.bincode_config, MAX_HEADER_SIZE).await? {
+            Response::Ok(len) => Response::Ok(Download { rx, remaining: len }),
+            Response::Err(e) => Response::Err(e),
+        };
+
+        Ok(rsp)
+    }
+
+    /// Streams `size` bytes from `data` into a new file. With `hash`, the
+    /// server refuses to commit content that does not match it.
+    pub async fn upload<R: AsyncRead + Unpin>(
+        &self,
+        file_name: String,
+        tags: Vec<Tag>,
+        replace: bool,
+        size: u64,
+        hash: Option<ContentHash>,
+        mut data: R,
+    ) -> Result<Response<File>, Error> {
+        self.require("upload").await?;
+
+        let tags = tags.into_iter().map(Into::into).collect();
+        let cmd = Cmd::Upload {
+            file_name,
+            tags,
+            replace,
+            size,
+            hash,
+        };
+
+        let (mut tx, rx) = self.open().await?;
+        frame::write(&mut tx, &cmd, self.bincode_config).await?;
+
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = match data.read(&mut buf).await {
+                Ok(n) => n,
+                Err(e) => {
+                    tx.reset(0u32.into()).ok();
+                    return Err(e.into());
+                }
+            };
+
+            if n == 0 {
+                tx.finish()?;
+                break;
+            }
+
+            match tx.write_all(&buf[0..n]).await {
+                Ok(()) => {}
+                // The server stopped reading, its response says why
+                Err(WriteError::Stopped(_)) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        self.recv(rx).await
+    }
+
+    pub async fn has_content(&self, hash: ContentHash) -> Result<Response<bool>, Error> {
+        self.require("dedup").await?;
+        self.send(Cmd::HasContent { hash }).await
+    }
+
+    pub async fn commit_hash(
+        &self,
+        hash: ContentHash,
+        file_name: String,
+        tags: Vec<Tag>,
+        replace: bool,
+    ) -> Result<Response<File>, Error> {
+        self.require("dedup").await?;
+
+        let tags = tags.into_iter().map(Into::into).collect();
+        self.send(Cmd::CommitHash {
+            hash,
+            file_name,
+            tags,
+            replace,
+        })
+        .await
+    }
+
+    pub async fn stats(&self) -> Result<Response<Stats>, Error> {
+        self.require("stats").await?;
+        self.send(Cmd::Stats).await
+    }
+
+    pub async fn rotate_keys(&self) -> Result<Response<RotationReport>, Error> {
+        self.require("rotate-keys").await?;
+        self.send(Cmd::RotateKeys).await
+    }
+
+    pub async fn versions(&self, name: String) -> Result<Response<Vec<FileVersion>>, Error> {
+        self.require("versions").await?;
+        self.send(Cmd::Versions { name }).await
+    }
+
+    pub async fn restore_version(
+        &self,
+        name: String,
+        version: u64,
+    ) -> Result<Response<File>, Error> {
+        self.require("versions").await?;
+        self.send(Cmd::RestoreVersion { name, version }).await
+    }
+
+    /// Sets how many versions of `name` are kept, or restores the server
+    /// default with `None`.
+    pub async fn set_version_limit(
+        &self,
+        name: String,
+        keep: Option<u32>,
+    ) -> Result<Response<String>, Error> {
+        self.require("versions").await?;
+        self.send(Cmd::SetVersionLimit { name, keep }).await
+    }
+
+    /// Renames a file together with its version history. With `replace`, an
+    /// existing file named `to` is moved to the trash.
+    pub async fn rename(
+        &self,
+        from: String,
+        to: String,
+        replace: bool,
+    ) -> Result<Response<File>, Error> {
+        self.require("rename").await?;
+        self.send(Cmd::Rename { from, to, replace }).await
+    }
+
+    /// Copies the current version of a file, keeping its tags unless `tags`
+    /// is given.
+    pub async fn copy(
+        &self,
+        from: String,
+        to: String,
+        tags: Option<Vec<Tag>>,
+    ) -> Result<Response<File>, Error> {
+        self.require("copy").await?;
+
+        let tags = tags.map(|tags| tags.into_iter().map(Into::into).collect());
+        self.send(Cmd::Copy { from, to, tags }).await
+    }
+
+    /// Adds and removes tags on every selected file. Fails without changes if
+    /// a file would be left without tags.
+    pub async fn edit_tags(
+        &self,
+        files: FileSelection,
+        add: Vec<Tag>,
+        remove: Vec<Tag>,
+    ) -> Result<Response<Vec<FileDescription>>, Error> {
+        self.require("edit-tags").await?;
+
+        let add = add.into_iter().map(Into::into).collect();
+        let remove = remove.into_iter().map(Into::into).collect();
+        self.send(Cmd::EditTags { files, add, remove }).await
+    }
+
+    /// Lists tags with the number and total size of current files carrying
+    /// each.
+    pub async fn tag_stats(&self) -> Result<Response<Vec<TagInfo>>, Error> {
+        self.require("tag-stats").await?;
+        self.send(Cmd::TagStats).await
+    }
+
+    pub async fn rename_tag(&self, from: Tag, to: Tag) -> Result<Response<String>, Error> {
+        self.require("tag-admin").await?;
+
+        let (from, to) = (from.into(), to.into());
+        self.send(Cmd::RenameTag { from, to }).await
+    }
+
+    /// Moves every file tagged `from` to `into` and deletes `from`.
+    pub async fn merge_tags(&self, from: Tag, into: Tag) -> Result<Response<String>, Error> {
+        self.require("tag-admin").await?;
+
+        let (from, into) = (from.into(), into.into());
+        self.send(Cmd::MergeTags { from, into }).await
+    }
+
+    /// Deletes a tag from every file. Refused if a file would be left without
+    /// tags, unless `force` is set.
+    pub async fn delete_tag(&self, name: Tag, force: bool) -> Result<Response<String>, Error> {
+        self.require("tag-admin").await?;
+        self.send(Cmd::DeleteTag {
+            name: name.into(),
+            force,
+        })
+        .await
+    }
+
+    pub async fn trash(&self) -> Result<Response<Vec<TrashEntry>>, Error> {
+        self.require("trash").await?;
+        self.send(Cmd::Trash).await
+    }
+
+    /// Restores a trashed file under its original name, or under `name`.
+    pub async fn restore_trash(
+        &self,
+        id: u64,
+        name: Option<String>,
+    ) -> Result<Response<File>, Error> {
+        self.require("trash").await?;
+        self.send(Cmd::RestoreTrash { id, name }).await
+    }
+
+    /// Purges one trash entry, or the whole trash with `None`.
+    pub async fn purge_trash(&self, id: Option<u64>) -> Result<Response<u64>, Error> {
+        self.require("trash").await?;
+        self.send(Cmd::PurgeTrash { id }).await
+    }
+
+    pub async fn scrub(
+        &self,
+        after: Option<ContentHash>,
+        limit: u32,
+        quarantine: bool,
+    ) -> Result<Response<ScrubReport>, Error> {
+        self.require("scrub").await?;
+
+        self.send(Cmd::Scrub {
+            after,
+            limit,
+            quarantine,
+        })
+        .await
+    }
+
+    pub async fn close(&self) {
+        if let Some(session) = self.session.lock().await.take() {
+            session.conn.close(0u32.into(), b"bye");
+        }
+    }
+
+    async fn session(&self) -> Result<(Connection, ServerInfo), Error> {
+        let mut session = self.session.lock().await;
+        if let Some(session) = session.as_ref().filter(|s| s.conn.close_reason().is_none()) {
+            return Ok((session.conn.clone(), session.info.clone()));
+        }
+
         let conn = match &self.server {
-            Either::Left(node_id) => self.endpoint.connect(node_id.clone(), ALPN).await?,
-            Either::Right(node_addr) => self.endpoint.connect(node_addr.clone(), ALPN).await?,
+            Either::Left(node_addr) => self.endpoint.connect(node_addr.clone(), ALPN).await?,
+            Either::Right(node_id) => self.endpoint.connect(*node_id, ALPN).await?,
+        };
+
+        let info = self.handshake(&conn).await?;
+
+        *session = Some(Session {
+            conn: conn.clone(),
+            info: info.clone(),
+        });
+
+        Ok((conn, info))
+    }
+
+    async fn handshake(&self, conn: &Connection) -> Result<ServerInfo, Error> {
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
         };
 
         let (mut tx, mut rx) = conn.open_bi().await?;
-        tx.write_all(&json).await?;
+        frame::write(&mut tx, &hello, self.bincode_config).await?;
+        tx.finish()?;
+
+        let info: ServerInfo = frame::read(&mut rx, self.bincode_config, MAX_HEADER_SIZE).await?;
+        if info.version != PROTOCOL_VERSION {
+            conn.close(0u32.into(), b"incompatible");
+            return Err(Error::IncompatibleVersion {
+                client: PROTOCOL_VERSION,
+                server: info.version,
+            });
+        }
+
+        Ok(info)
+    }
+
+    async fn require(&self, capability: &str) -> Result<(), Error> {
+        let (_, info) = self.session().await?;
+        if !info.supports(capability) {
+            return Err(Error::Unsupported(capability.to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<(SendStream, RecvStream), Error> {
+        let (conn, _) = self.session().await?;
+        if let Ok(streams) = conn.open_bi().await {
+            return Ok(streams);
+        }
+
+        // The connection was lost since it was last used, so reconnect once
+        conn.close(0u32.into(), b"bye");
+        let (conn, _) = self.session().await?;
+        let streams = conn.open_bi().await?;
+        Ok(streams)
+    }
+
+    async fn send<R: Decode<()>>(&self, cmd: Cmd) -> Result<R, Error> {
+        let (mut tx, rx) = self.open().await?;
+        frame::write(&mut tx, &cmd, self.bincode_config).await?;
         tx.finish()?;
 
+        self.recv(rx).await
+    }
+
+    async fn recv<R: Decode<()>>(&self, mut rx: RecvStream) -> Result<R, Error> {
         let mut data = vec![];
         while let Some(chunk) = rx.read_chunk(CHUNK_SIZE, true).await? {
             let mut bytes = chunk.bytes.to_vec();
             data.append(&mut bytes);
         }
 
-        conn.close(0u32.into(), b"bye");
-
         let rsp = bincode::decode_from_slice(&data, self.bincode_config)?.0;
         Ok(rsp)
     }
 }
+
+pub struct Download {
+    rx: RecvStream,
+    remaining: u64,
+}
+
+impl Download {
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+}
+
+impl AsyncRead for Download {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let capacity = buf.remaining();
+        match Pin::new(&mut self.rx).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                let n = (buf.filled().len() - filled) as u64;
+                if n == 0 && capacity > 0 && self.remaining > 0 {
+                    let e = std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Download ended early",
+                    );
+                    return Poll::Ready(Err(e));
+                }
+
+                self.remaining = self.remaining.saturating_sub(n);
+                Poll::Ready(Ok(()))
+            }
+            p => p,
+        }
+    }
+}
@@ -1,11 +1,37 @@
-use std::str::FromStr;
+use std::{fmt::Display, str::FromStr};
 
-use bincode::{Decode, Encode};
+use bincode::{
+    Decode, Encode,
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+};
+use uuid::Uuid;
 
-use super::db;
+use super::{Error, db};
 
 pub const ALPN: &[u8] = b"stash";
 
+pub const PROTOCOL_VERSION: u32 = 7;
+
+pub const CAPABILITIES: &[&str] = &[
+    "stream",
+    "upload",
+    "scrub",
+    "list-blobs",
+    "dedup",
+    "stats",
+    "write-blob",
+    "rotate-keys",
+    "versions",
+    "trash",
+    "rename",
+    "copy",
+    "edit-tags",
+    "tag-admin",
+    "tag-stats",
+];
+
 pub type SHA256 = String;
 
 #[derive(Clone, Debug)]
@@ -14,23 +40,51 @@ pub enum Either<A, B> {
     Right(B),
 }
 
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct ServerInfo {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+}
+
+impl ServerInfo {
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
 #[derive(Clone, Debug, Decode, Encode)]
 pub enum Cmd {
     Tags,
     CreateBlob,
     DescribeBlob {
-        name: String,
+        name: BlobId,
     },
     AppendBlob {
-        name: String,
+        name: BlobId,
+        offset: u64,
         data: Vec<u8>,
     },
     CommitBlob {
-        name: String,
+        name: BlobId,
         file_name: String,
         tags: Vec<String>,
         replace: bool,
+        hash: Option<ContentHash>,
+        size: Option<u64>,
     },
+    ListBlobs,
     GcBlobs,
     List {
         tag: String,
@@ -47,16 +101,129 @@ pub enum Cmd {
         name: String,
     },
     Download {
-        hash: SHA256,
+        hash: ContentHash,
         start: u64,
         len: u64,
     },
+    Stream {
+        hash: ContentHash,
+        start: u64,
+        len: u64,
+    },
+    Upload {
+        file_name: String,
+        tags: Vec<String>,
+        replace: bool,
+        size: u64,
+        hash: Option<ContentHash>,
+    },
+    Scrub {
+        after: Option<ContentHash>,
+        limit: u32,
+        quarantine: bool,
+    },
+    HasContent {
+        hash: ContentHash,
+    },
+    CommitHash {
+        hash: ContentHash,
+        file_name: String,
+        tags: Vec<String>,
+        replace: bool,
+    },
+    Stats,
+    WriteBlob {
+        name: BlobId,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    RotateKeys,
+    Versions {
+        name: String,
+    },
+    RestoreVersion {
+        name: String,
+        version: u64,
+    },
+    SetVersionLimit {
+        name: String,
+        keep: Option<u32>,
+    },
+    Trash,
+    RestoreTrash {
+        id: u64,
+        name: Option<String>,
+    },
+    PurgeTrash {
+        id: Option<u64>,
+    },
+    Rename {
+        from: String,
+        to: String,
+        replace: bool,
+    },
+    Copy {
+        from: String,
+        to: String,
+        tags: Option<Vec<String>>,
+    },
+    EditTags {
+        files: FileSelection,
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    RenameTag {
+        from: String,
+        to: String,
+    },
+    MergeTags {
+        from: String,
+        into: String,
+    },
+    DeleteTag {
+        name: String,
+        force: bool,
+    },
+    TagStats,
+}
+
+/// Files addressed by a bulk command, either by name or by the same tag and
+/// term match as `Search`.
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub enum FileSelection {
+    Names(Vec<String>),
+    Search { tag: String, term: String },
 }
 
 #[derive(Clone, Debug, Decode, Encode, PartialEq)]
 pub enum Response<R> {
     Ok(R),
-    Err(String),
+    Err(Failure),
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub enum Failure {
+    NotFound(String),
+    Conflict(String),
+    InvalidArgument(String),
+    OutOfRange(String),
+    Unauthorized(String),
+    Quota(String),
+    Internal(String),
+}
+
+impl Failure {
+    pub fn message(&self) -> &str {
+        match self {
+            Self::NotFound(m)
+            | Self::Conflict(m)
+            | Self::InvalidArgument(m)
+            | Self::OutOfRange(m)
+            | Self::Unauthorized(m)
+            | Self::Quota(m)
+            | Self::Internal(m) => m,
+        }
+    }
 }
 
 impl Response<String> {
@@ -66,10 +233,34 @@ impl Response<String> {
 }
 
 impl<R> Response<R> {
-    pub fn res(self) -> anyhow::Result<R> {
+    pub fn not_found(msg: impl Into<String>) -> Self {
+        Self::Err(Failure::NotFound(msg.into()))
+    }
+
+    pub fn conflict(msg: impl Into<String>) -> Self {
+        Self::Err(Failure::Conflict(msg.into()))
+    }
+
+    pub fn invalid_argument(msg: impl Into<String>) -> Self {
+        Self::Err(Failure::InvalidArgument(msg.into()))
+    }
+
+    pub fn out_of_range(msg: impl Into<String>) -> Self {
+        Self::Err(Failure::OutOfRange(msg.into()))
+    }
+
+    pub fn unauthorized(msg: impl Into<String>) -> Self {
+        Self::Err(Failure::Unauthorized(msg.into()))
+    }
+
+    pub fn quota(msg: impl Into<String>) -> Self {
+        Self::Err(Failure::Quota(msg.into()))
+    }
+
+    pub fn res(self) -> Result<R, Error> {
         match self {
             Self::Ok(r) => Ok(r),
-            Self::Err(e) => Err(anyhow::anyhow!(e)),
+            Self::Err(e) => Err(e.into()),
         }
     }
 
@@ -80,7 +271,7 @@ impl<R> Response<R> {
         }
     }
 
-    pub fn err(self) -> String {
+    pub fn err(self) -> Failure {
         match self {
             Self::Ok(_) => panic!("`err` called on Response::Ok"),
             Self::Err(e) => e,
@@ -97,9 +288,9 @@ impl Tag {
     }
 }
 
-impl Into<String> for Tag {
-    fn into(self) -> String {
-        self.0
+impl From<Tag> for String {
+    fn from(tag: Tag) -> Self {
+        tag.0
     }
 }
 
@@ -117,11 +308,108 @@ impl FromStr for Tag {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlobId(Uuid);
+
+impl BlobId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for BlobId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for BlobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.hyphenated())
+    }
+}
+
+impl FromStr for BlobId {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let id = Uuid::try_parse(name).map_err(|_| ())?;
+        if id.hyphenated().to_string() != name {
+            return Err(());
+        }
+
+        Ok(Self(id))
+    }
+}
+
+impl Encode for BlobId {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.to_string().encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for BlobId {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let name = String::decode(decoder)?;
+        Self::from_str(&name).map_err(|_| DecodeError::OtherString(format!("Invalid blob {name}")))
+    }
+}
+
+bincode::impl_borrow_decode!(BlobId);
+
+#[derive(Clone, Debug, Encode, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ContentHash(pub(crate) SHA256);
+
+impl ContentHash {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for ContentHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<ContentHash> for String {
+    fn from(hash: ContentHash) -> Self {
+        hash.0
+    }
+}
+
+impl FromStr for ContentHash {
+    type Err = ();
+
+    fn from_str(hash: &str) -> Result<Self, Self::Err> {
+        if hash.len() != 64 {
+            return Err(());
+        }
+
+        for c in hash.chars() {
+            if !c.is_ascii_digit() && !('a'..='f').contains(&c) {
+                return Err(());
+            }
+        }
+
+        Ok(Self(hash.to_string()))
+    }
+}
+
+impl<Context> Decode<Context> for ContentHash {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let hash = String::decode(decoder)?;
+        Self::from_str(&hash).map_err(|_| DecodeError::OtherString(format!("Invalid hash {hash}")))
+    }
+}
+
+bincode::impl_borrow_decode!(ContentHash);
+
 #[derive(Clone, Debug, Decode, Encode, PartialEq)]
 pub struct File {
     pub name: String,
     pub size: u64,
-    pub hash: SHA256,
+    pub hash: ContentHash,
     pub created: i64,
 }
 
@@ -130,7 +418,7 @@ impl From<db::FileDesc> for File {
         Self {
             name: value.name,
             size: value.size as u64,
-            hash: value.hash,
+            hash: ContentHash(value.hash),
             created: value.created.and_utc().timestamp(),
         }
     }
@@ -140,7 +428,7 @@ impl From<db::FileDesc> for File {
 pub struct FileDescription {
     pub name: String,
     pub size: u64,
-    pub hash: SHA256,
+    pub hash: ContentHash,
     pub created: i64,
     pub tags: Vec<String>,
 }
@@ -150,7 +438,7 @@ impl FileDescription {
         Self {
             name: file.name,
             size: file.size as u64,
-            hash: file.hash,
+            hash: ContentHash(file.hash),
             created: file.created.and_utc().timestamp(),
             tags,
         }
@@ -158,27 +446,170 @@ impl FileDescription {
 }
 
 #[derive(Clone, Debug, Decode, Encode, PartialEq)]
-pub struct Blob {
+pub struct FileVersion {
+    pub version: u64,
+    pub size: u64,
+    pub hash: ContentHash,
+    pub uploader: String,
+    pub created: i64,
+    pub tags: Vec<String>,
+    pub current: bool,
+}
+
+impl FileVersion {
+    pub fn new(file: db::FileVersion, tags: Vec<String>) -> Self {
+        Self {
+            version: file.version as u64,
+            size: file.size as u64,
+            hash: ContentHash(file.hash),
+            uploader: file.uploader,
+            created: file.created.and_utc().timestamp(),
+            tags,
+            current: !file.archived,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct TagInfo {
     pub name: String,
+    pub files: u64,
     pub size: u64,
 }
 
+impl From<db::TagStats> for TagInfo {
+    fn from(value: db::TagStats) -> Self {
+        Self {
+            name: value.name,
+            files: value.files as u64,
+            size: value.size as u64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct TrashEntry {
+    pub id: u64,
+    pub name: String,
+    pub size: u64,
+    pub hash: ContentHash,
+    pub versions: u64,
+    pub deleted_by: String,
+    pub deleted: i64,
+}
+
+impl From<db::TrashDesc> for TrashEntry {
+    fn from(value: db::TrashDesc) -> Self {
+        Self {
+            id: value.id as u64,
+            name: value.name,
+            size: value.size as u64,
+            hash: ContentHash(value.hash),
+            versions: value.versions as u64,
+            deleted_by: value.deleted_by,
+            deleted: value.deleted.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct Blob {
+    pub name: BlobId,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct BlobInfo {
+    pub name: BlobId,
+    pub owner: String,
+    pub size: u64,
+    pub created: i64,
+    pub updated: i64,
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct GcReport {
+    pub removed: Vec<String>,
+    pub freed: u64,
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct ScrubReport {
+    pub checked: u64,
+    pub corrupted: Vec<ContentHash>,
+    pub missing: Vec<ContentHash>,
+    pub orphaned: Vec<String>,
+    pub corrupted_chunks: Vec<ContentHash>,
+    pub orphaned_chunks: Vec<String>,
+    pub next: Option<ContentHash>,
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct RotationReport {
+    pub rewrapped: u64,
+}
+
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct Stats {
+    pub files: u64,
+    pub contents: u64,
+    pub chunks: u64,
+    pub logical_size: u64,
+    pub content_size: u64,
+    pub stored_size: u64,
+}
+
+impl From<db::Stats> for Stats {
+    fn from(value: db::Stats) -> Self {
+        Self {
+            files: value.files as u64,
+            contents: value.contents as u64,
+            chunks: value.chunks as u64,
+            logical_size: value.logical_size as u64,
+            content_size: value.content_size as u64,
+            stored_size: value.stored_size as u64,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::Tag;
+    use crate::{BlobId, ContentHash, Tag};
     use std::str::FromStr;
 
     #[test]
     fn tag_validation() {
         let t = Tag::from_str("test-1");
-        assert!(matches!(t, Ok(_)));
+        assert!(t.is_ok());
         assert_eq!(t.unwrap().tag(), "test-1");
 
         let t = Tag::from_str("1-test");
-        assert!(matches!(t, Ok(_)));
+        assert!(t.is_ok());
         assert_eq!(t.unwrap().tag(), "1-test");
 
         let t = Tag::from_str(";notvalid");
-        assert!(matches!(t, Err(_)));
+        assert!(t.is_err());
+    }
+
+    #[test]
+    fn blob_id_validation() {
+        let id = BlobId::new();
+        assert_eq!(BlobId::from_str(&id.to_string()), Ok(id));
+
+        assert!(BlobId::from_str("936DA01F-9ABD-4D9D-80C7-02AF85C822A8").is_err());
+        assert!(BlobId::from_str("936da01f9abd4d9d80c702af85c822a8").is_err());
+        assert!(BlobId::from_str("../server.db").is_err());
+    }
+
+    #[test]
+    fn content_hash_validation() {
+        let hash = "a".repeat(64);
+        let h = ContentHash::from_str(&hash);
+        assert!(h.is_ok());
+        assert_eq!(h.unwrap().as_str(), hash);
+
+        assert!(ContentHash::from_str(&"A".repeat(64)).is_err());
+        assert!(ContentHash::from_str(&"a".repeat(63)).is_err());
+        assert!(ContentHash::from_str(&format!("../{}", "a".repeat(61))).is_err());
     }
 }
@@ -0,0 +1,171 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{Error, blocks::Blocks};
+
+pub const FRAME_SIZE: u64 = 1 << 20;
+
+// Magic numbers of formats that are already compressed
+const COMPRESSED_MAGIC: &[&[u8]] = &[
+    b"\x1f\x8b",           // gzip
+    b"\x28\xb5\x2f\xfd",   // zstd
+    b"BZh",                // bzip2
+    b"\xfd7zXZ\x00",       // xz
+    b"7z\xbc\xaf\x27\x1c", // 7z
+    b"PK\x03\x04",         // zip and zip-based documents
+    b"\x89PNG",            // png
+    b"\xff\xd8\xff",       // jpeg
+    b"GIF8",               // gif
+    b"RIFF",               // webp, avi, wav
+    b"OggS",               // ogg
+    b"fLaC",               // flac
+    b"ID3",                // mp3
+    b"\x1a\x45\xdf\xa3",   // matroska, webm
+];
+
+/// Decides which committed content is stored zstd-compressed.
+///
+/// Content is compressed when it is at least `min_size` bytes, carries one
+/// of `tags` (or `tags` is empty), does not start with the signature of an
+/// already compressed format, and shrinks to at most 90% of its size.
+#[derive(Clone, Debug)]
+pub struct CompressionPolicy {
+    pub level: i32,
+    pub min_size: u64,
+    pub tags: Vec<String>,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            level: 3,
+            min_size: 4096,
+            tags: vec![],
+        }
+    }
+}
+
+impl CompressionPolicy {
+    pub(crate) fn applies(&self, size: u64, tags: &[String]) -> bool {
+        size >= self.min_size
+            && (self.tags.is_empty() || tags.iter().any(|t| self.tags.contains(t)))
+    }
+
+    /// Compresses `reader` into one zstd frame per `FRAME_SIZE` bytes.
+    pub(crate) fn compressor<R: AsyncRead + Unpin>(&self, reader: R) -> Compressor<R> {
+        Compressor {
+            reader,
+            level: self.level,
+            frames: vec![],
+            skipped: false,
+        }
+    }
+}
+
+pub(crate) struct Compressor<R> {
+    reader: R,
+    level: i32,
+    frames: Vec<(u64, u64)>,
+    skipped: bool,
+}
+
+impl<R: AsyncRead + Unpin> Compressor<R> {
+    /// Returns the next compressed frame, or `None` at the end of the input
+    /// or once the input turns out to be in an already compressed format.
+    pub(crate) async fn next(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        if self.skipped {
+            return Ok(None);
+        }
+
+        let mut frame = Vec::with_capacity(FRAME_SIZE as usize);
+        (&mut self.reader)
+            .take(FRAME_SIZE)
+            .read_to_end(&mut frame)
+            .await?;
+        if frame.is_empty() {
+            return Ok(None);
+        }
+
+        if self.frames.is_empty() && COMPRESSED_MAGIC.iter().any(|m| frame.starts_with(m)) {
+            self.skipped = true;
+            return Ok(None);
+        }
+
+        let compressed = zstd::bulk::compress(&frame, self.level)?;
+        self.frames
+            .push((frame.len() as u64, compressed.len() as u64));
+
+        Ok(Some(compressed))
+    }
+
+    /// The raw and compressed size of each frame, if compression was
+    /// worthwhile.
+    pub(crate) fn finish(self) -> Option<Vec<(u64, u64)>> {
+        let raw: u64 = self.frames.iter().map(|(raw, _)| raw).sum();
+        let compressed: u64 = self.frames.iter().map(|(_, size)| size).sum();
+        if self.skipped || compressed * 10 > raw * 9 {
+            return None;
+        }
+
+        Some(self.frames)
+    }
+}
+
+/// Decompresses consecutive frames with the given `(raw, compressed)` sizes,
+/// dropping the first `skip` bytes of output.
+pub(crate) fn decompress<R: AsyncRead + Send + Unpin>(
+    reader: R,
+    frames: Vec<(u64, u64)>,
+    skip: usize,
+) -> impl AsyncRead + Send + Unpin {
+    let capacity = frames.iter().map(|(raw, _)| *raw).max().unwrap_or(0) as usize;
+    let sizes = frames.into_iter().map(|(_, size)| size as usize);
+
+    Blocks::new(reader, sizes, move |frame| {
+        zstd::bulk::decompress(frame, capacity)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    })
+    .with_skip(skip)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::{CompressionPolicy, FRAME_SIZE, decompress};
+
+    #[tokio::test]
+    async fn frame_round_trip() {
+        let policy = CompressionPolicy::default();
+        let data: Vec<u8> = (0..FRAME_SIZE * 5 / 2)
+            .map(|i| b"abcdefgh"[(i % 8) as usize] + (i / 100_000) as u8)
+            .collect();
+
+        let mut compressor = policy.compressor(data.as_slice());
+        let mut compressed = vec![];
+        while let Some(frame) = compressor.next().await.unwrap() {
+            compressed.extend(frame);
+        }
+
+        let frames = compressor.finish().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].0, FRAME_SIZE / 2);
+        assert_eq!(
+            compressed.len() as u64,
+            frames.iter().map(|(_, size)| size).sum::<u64>()
+        );
+
+        let mut decompressed = vec![];
+        decompress(compressed.as_slice(), frames, 0)
+            .read_to_end(&mut decompressed)
+            .await
+            .unwrap();
+        assert_eq!(decompressed, data);
+
+        let gzip = [b"\x1f\x8b".as_slice(), &data].concat();
+        let mut compressor = policy.compressor(gzip.as_slice());
+        assert!(compressor.next().await.unwrap().is_none());
+        assert!(compressor.finish().is_none());
+    }
+}
@@ -1,9 +1,23 @@
+mod blob;
+mod chunk;
 mod file;
 mod file_content;
 mod file_tag;
+mod frame;
+mod journal;
+mod stats;
 mod tag;
+mod trash;
+mod version_limit;
 
-pub use file::{File, FileDesc};
+pub use blob::Blob;
+pub use chunk::Chunk;
+pub use file::{File, FileDesc, FileVersion};
 pub use file_content::FileContent;
 pub use file_tag::FileTag;
-pub use tag::Tag;
+pub use frame::Frame;
+pub use journal::Journal;
+pub use stats::Stats;
+pub use tag::{Tag, TagStats};
+pub use trash::{Trash, TrashDesc};
+pub use version_limit::VersionLimit;
@@ -0,0 +1,68 @@
+use chrono::NaiveDateTime;
+use sqlx::{Executor, Sqlite, prelude::FromRow, query, query_as};
+
+#[allow(dead_code)]
+#[derive(Debug, FromRow)]
+pub struct Blob {
+    pub id: i64,
+    pub name: String,
+    pub owner: String,
+    pub created: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+impl Blob {
+    pub async fn all<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+    ) -> Result<Vec<Blob>, sqlx::Error> {
+        query_as::<_, Blob>("SELECT * FROM blobs")
+            .fetch_all(conn)
+            .await
+    }
+
+    pub async fn by_name<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+    ) -> Result<Option<Blob>, sqlx::Error> {
+        query_as::<_, Blob>("SELECT * FROM blobs WHERE name = $1")
+            .bind(name)
+            .fetch_optional(conn)
+            .await
+    }
+
+    pub async fn insert<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+        owner: &str,
+    ) -> Result<Blob, sqlx::Error> {
+        query_as::<_, Blob>(
+            "INSERT INTO blobs (name, owner, created, updated) VALUES ($1, $2, datetime('now'), datetime('now')) RETURNING *",
+        )
+        .bind(name)
+        .bind(owner)
+        .fetch_one(conn)
+        .await
+    }
+
+    pub async fn touch<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+    ) -> Result<u64, sqlx::Error> {
+        query("UPDATE blobs SET updated = datetime('now') WHERE name = $1")
+            .bind(name)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    pub async fn delete<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+    ) -> Result<u64, sqlx::Error> {
+        query("DELETE FROM blobs WHERE name = $1")
+            .bind(name)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+}
@@ -0,0 +1,98 @@
+use chrono::NaiveDateTime;
+use sqlx::{Executor, Sqlite, prelude::FromRow, query, query_as};
+
+use crate::SHA256;
+
+#[allow(dead_code)]
+#[derive(Debug, FromRow)]
+pub struct Chunk {
+    pub id: i64,
+    pub hash: SHA256,
+    pub size: i64,
+    pub created: NaiveDateTime,
+}
+
+impl Chunk {
+    pub async fn by_hash<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        hash: &str,
+    ) -> Result<Option<Chunk>, sqlx::Error> {
+        query_as::<_, Chunk>("SELECT * FROM chunks WHERE hash = $1")
+            .bind(hash)
+            .fetch_optional(conn)
+            .await
+    }
+
+    pub async fn for_content<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        content_id: i64,
+    ) -> Result<Vec<Chunk>, sqlx::Error> {
+        query_as::<_, Chunk>(
+            r#"
+                SELECT c.* FROM chunks c
+                JOIN content_chunks cc ON cc.chunk_id = c.id
+                WHERE cc.content_id = $1
+                ORDER BY cc.seq
+            "#,
+        )
+        .bind(content_id)
+        .fetch_all(conn)
+        .await
+    }
+
+    pub async fn orphaned_by<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        content_id: i64,
+    ) -> Result<Vec<Chunk>, sqlx::Error> {
+        query_as::<_, Chunk>(
+            r#"
+                SELECT * FROM chunks
+                WHERE id IN (SELECT chunk_id FROM content_chunks WHERE content_id = $1)
+                AND id NOT IN (SELECT chunk_id FROM content_chunks WHERE content_id != $1)
+            "#,
+        )
+        .bind(content_id)
+        .fetch_all(conn)
+        .await
+    }
+
+    pub async fn insert<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        hash: &str,
+        size: i64,
+    ) -> Result<Chunk, sqlx::Error> {
+        query_as::<_, Chunk>(
+            "INSERT INTO chunks (hash, size, created) VALUES ($1, $2, datetime('now')) RETURNING *",
+        )
+        .bind(hash)
+        .bind(size)
+        .fetch_one(conn)
+        .await
+    }
+
+    pub async fn link<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        content_id: i64,
+        seq: i64,
+        chunk_id: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query("INSERT INTO content_chunks (content_id, seq, chunk_id) VALUES ($1, $2, $3)")
+            .bind(content_id)
+            .bind(seq)
+            .bind(chunk_id)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    pub async fn delete<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        id: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query("DELETE FROM chunks WHERE id = $1")
+            .bind(id)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+}
@@ -11,6 +11,21 @@ pub struct File {
     pub content_id: i64,
     pub uploader: String,
     pub created: NaiveDateTime,
+    pub version: i64,
+    pub archived: bool,
+    pub trash_id: Option<i64>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct FileVersion {
+    pub id: i64,
+    pub content_id: i64,
+    pub version: i64,
+    pub size: i64,
+    pub hash: SHA256,
+    pub uploader: String,
+    pub created: NaiveDateTime,
+    pub archived: bool,
 }
 
 #[derive(Debug, FromRow)]
@@ -33,7 +48,7 @@ impl File {
                 SELECT f.id, f.content_id, f.name, c.size, c.hash, f.created
                 FROM files f
                 JOIN file_contents c ON c.id = f.content_id
-                WHERE f.name = $1
+                WHERE f.name = $1 AND f.archived = 0 AND f.trash_id IS NULL
             "#,
         )
         .bind(name)
@@ -48,7 +63,17 @@ impl File {
         uploader: &str,
     ) -> Result<File, sqlx::Error> {
         query_as::<_, File>(
-            "INSERT INTO files (name, content_id, uploader, created) VALUES ($1, $2, $3, datetime('now')) RETURNING *",
+            r#"
+                INSERT INTO files (name, content_id, uploader, created, version)
+                VALUES (
+                    $1, $2, $3, datetime('now'),
+                    (
+                        SELECT COALESCE(MAX(version), 0) + 1 FROM files
+                        WHERE name = $1 AND trash_id IS NULL
+                    )
+                )
+                RETURNING *
+            "#,
         )
         .bind(name)
         .bind(content_id)
@@ -57,6 +82,107 @@ impl File {
         .await
     }
 
+    pub async fn archive<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        id: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query("UPDATE files SET archived = 1 WHERE id = $1")
+            .bind(id)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    pub async fn versions<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+    ) -> Result<Vec<FileVersion>, sqlx::Error> {
+        query_as::<_, FileVersion>(
+            r#"
+                SELECT f.id, f.content_id, f.version, c.size, c.hash, f.uploader, f.created, f.archived
+                FROM files f
+                JOIN file_contents c ON c.id = f.content_id
+                WHERE f.name = $1 AND f.trash_id IS NULL
+                ORDER BY f.version DESC
+            "#,
+        )
+        .bind(name)
+        .fetch_all(conn)
+        .await
+    }
+
+    /// Archived versions of `name` beyond the `keep` most recent versions.
+    pub async fn expired<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+        keep: i64,
+    ) -> Result<Vec<File>, sqlx::Error> {
+        query_as::<_, File>(
+            r#"
+                SELECT * FROM files
+                WHERE name = $1 AND archived = 1 AND trash_id IS NULL
+                ORDER BY version DESC
+                LIMIT -1 OFFSET $2
+            "#,
+        )
+        .bind(name)
+        .bind(keep - 1)
+        .fetch_all(conn)
+        .await
+    }
+
+    /// Moves every version of `name` into the trash entry `trash_id`.
+    pub async fn trash<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+        trash_id: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query("UPDATE files SET trash_id = $2 WHERE name = $1 AND trash_id IS NULL")
+            .bind(name)
+            .bind(trash_id)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    /// Renames every live version of `from` to `to`.
+    pub async fn rename<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        from: &str,
+        to: &str,
+    ) -> Result<u64, sqlx::Error> {
+        query("UPDATE files SET name = $2 WHERE name = $1 AND trash_id IS NULL")
+            .bind(from)
+            .bind(to)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    pub async fn in_trash<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        trash_id: i64,
+    ) -> Result<Vec<File>, sqlx::Error> {
+        query_as::<_, File>("SELECT * FROM files WHERE trash_id = $1")
+            .bind(trash_id)
+            .fetch_all(conn)
+            .await
+    }
+
+    /// Takes every version out of the trash entry `trash_id` under `name`.
+    pub async fn untrash<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        trash_id: i64,
+        name: &str,
+    ) -> Result<u64, sqlx::Error> {
+        query("UPDATE files SET name = $2, trash_id = NULL WHERE trash_id = $1")
+            .bind(trash_id)
+            .bind(name)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
     pub async fn delete<'a, E: Executor<'a, Database = Sqlite>>(
         conn: E,
         id: i64,
@@ -80,7 +206,7 @@ impl File {
                 JOIN tags t ON t.id = ft.tag_id
                 JOIN files f ON f.id = ft.file_id
                 JOIN file_contents c ON c.id = f.content_id
-                WHERE t.name = $1 AND f.name LIKE $2
+                WHERE t.name = $1 AND f.name LIKE $2 AND f.archived = 0 AND f.trash_id IS NULL
                 ORDER BY f.name
             "#,
         )
@@ -11,6 +11,8 @@ pub struct FileContent {
     pub hash: SHA256,
     pub uploader: String,
     pub created: NaiveDateTime,
+    pub chunked: bool,
+    pub compressed: bool,
 }
 
 impl FileContent {
@@ -30,9 +32,23 @@ impl FileContent {
         .await
     }
 
+    pub async fn page<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        after: &str,
+        limit: i64,
+    ) -> Result<Vec<FileContent>, sqlx::Error> {
+        query_as::<_, FileContent>(
+            "SELECT * FROM file_contents WHERE hash > $1 ORDER BY hash LIMIT $2",
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(conn)
+        .await
+    }
+
     pub async fn by_hash<'a, E: Executor<'a, Database = Sqlite>>(
         conn: E,
-        hash: &SHA256,
+        hash: &str,
     ) -> Result<Option<FileContent>, sqlx::Error> {
         query_as::<_, FileContent>("SELECT * FROM file_contents WHERE hash = $1")
             .bind(hash)
@@ -43,15 +59,19 @@ impl FileContent {
     pub async fn insert<'a, E: Executor<'a, Database = Sqlite>>(
         conn: E,
         size: i64,
-        hash: &SHA256,
+        hash: &str,
         uploader: &str,
+        chunked: bool,
+        compressed: bool,
     ) -> Result<FileContent, sqlx::Error> {
         query_as::<_, FileContent>(
-            "INSERT INTO file_contents (size, hash, uploader, created) VALUES ($1, $2, $3, datetime('now')) RETURNING *",
+            "INSERT INTO file_contents (size, hash, uploader, created, chunked, compressed) VALUES ($1, $2, $3, datetime('now'), $4, $5) RETURNING *",
         )
         .bind(size)
         .bind(hash)
         .bind(uploader)
+        .bind(chunked)
+        .bind(compressed)
         .fetch_one(conn)
         .await
     }
@@ -1,4 +1,4 @@
-use sqlx::{Executor, Sqlite, prelude::FromRow, query_as};
+use sqlx::{Executor, Sqlite, prelude::FromRow, query, query_as, query_scalar};
 
 #[allow(dead_code)]
 #[derive(Debug, FromRow)]
@@ -28,6 +28,60 @@ impl FileTag {
         .await
     }
 
+    pub async fn delete<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        file_id: i64,
+        tag_id: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query("DELETE FROM file_tags WHERE file_id = $1 AND tag_id = $2")
+            .bind(file_id)
+            .bind(tag_id)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    /// Adds `into` to every file tagged `from`.
+    pub async fn merge<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        from: i64,
+        into: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query(
+            r#"
+                INSERT OR IGNORE INTO file_tags (file_id, tag_id)
+                SELECT file_id, $2 FROM file_tags WHERE tag_id = $1
+            "#,
+        )
+        .bind(from)
+        .bind(into)
+        .execute(conn)
+        .await
+        .map(|r| r.rows_affected())
+    }
+
+    /// Number of files whose only tag is `tag_id`, counting archived and
+    /// trashed versions, which could otherwise be restored without tags.
+    pub async fn sole<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        tag_id: i64,
+    ) -> Result<i64, sqlx::Error> {
+        query_scalar(
+            r#"
+                SELECT COUNT(*) FROM files f
+                WHERE EXISTS (
+                    SELECT 1 FROM file_tags ft WHERE ft.file_id = f.id AND ft.tag_id = $1
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM file_tags ft WHERE ft.file_id = f.id AND ft.tag_id != $1
+                )
+            "#,
+        )
+        .bind(tag_id)
+        .fetch_one(conn)
+        .await
+    }
+
     pub async fn for_file<'a, E: Executor<'a, Database = Sqlite>>(
         conn: E,
         file_id: i64,
@@ -0,0 +1,42 @@
+use sqlx::{Executor, Sqlite, prelude::FromRow, query, query_as};
+
+#[allow(dead_code)]
+#[derive(Debug, FromRow)]
+pub struct Frame {
+    pub id: i64,
+    pub content_id: i64,
+    pub seq: i64,
+    pub raw_size: i64,
+    pub size: i64,
+}
+
+impl Frame {
+    pub async fn for_content<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        content_id: i64,
+    ) -> Result<Vec<Frame>, sqlx::Error> {
+        query_as::<_, Frame>("SELECT * FROM content_frames WHERE content_id = $1 ORDER BY seq")
+            .bind(content_id)
+            .fetch_all(conn)
+            .await
+    }
+
+    pub async fn insert<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        content_id: i64,
+        seq: i64,
+        raw_size: i64,
+        size: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query(
+            "INSERT INTO content_frames (content_id, seq, raw_size, size) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(content_id)
+        .bind(seq)
+        .bind(raw_size)
+        .bind(size)
+        .execute(conn)
+        .await
+        .map(|r| r.rows_affected())
+    }
+}
@@ -0,0 +1,65 @@
+use chrono::NaiveDateTime;
+use sqlx::{Executor, Sqlite, prelude::FromRow, query, query_as};
+
+use crate::SHA256;
+
+#[allow(dead_code)]
+#[derive(Debug, FromRow)]
+pub struct Journal {
+    pub id: i64,
+    pub op: String,
+    pub hash: SHA256,
+    pub blob: Option<String>,
+    pub created: NaiveDateTime,
+}
+
+impl Journal {
+    pub const COMMIT: &str = "commit";
+    pub const DELETE: &str = "delete";
+    pub const DELETE_CHUNK: &str = "delete-chunk";
+
+    pub async fn all<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+    ) -> Result<Vec<Journal>, sqlx::Error> {
+        query_as::<_, Journal>("SELECT * FROM journal ORDER BY id")
+            .fetch_all(conn)
+            .await
+    }
+
+    pub async fn by_op<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        op: &str,
+    ) -> Result<Vec<Journal>, sqlx::Error> {
+        query_as::<_, Journal>("SELECT * FROM journal WHERE op = $1 ORDER BY id")
+            .bind(op)
+            .fetch_all(conn)
+            .await
+    }
+
+    pub async fn insert<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        op: &str,
+        hash: &str,
+        blob: Option<&str>,
+    ) -> Result<Journal, sqlx::Error> {
+        query_as::<_, Journal>(
+            "INSERT INTO journal (op, hash, blob, created) VALUES ($1, $2, $3, datetime('now')) RETURNING *",
+        )
+        .bind(op)
+        .bind(hash)
+        .bind(blob)
+        .fetch_one(conn)
+        .await
+    }
+
+    pub async fn delete<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        id: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query("DELETE FROM journal WHERE id = $1")
+            .bind(id)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+}
@@ -0,0 +1,41 @@
+use sqlx::{Executor, Sqlite, prelude::FromRow, query_as};
+
+#[derive(Debug, FromRow)]
+pub struct Stats {
+    pub files: i64,
+    pub contents: i64,
+    pub chunks: i64,
+    pub logical_size: i64,
+    pub content_size: i64,
+    pub stored_size: i64,
+}
+
+impl Stats {
+    pub async fn get<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+    ) -> Result<Stats, sqlx::Error> {
+        query_as::<_, Stats>(
+            r#"
+                SELECT
+                    (SELECT COUNT(*) FROM files WHERE archived = 0 AND trash_id IS NULL) AS files,
+                    (SELECT COUNT(*) FROM file_contents) AS contents,
+                    (SELECT COUNT(*) FROM chunks) AS chunks,
+                    (
+                        SELECT COALESCE(SUM(c.size), 0)
+                        FROM files f
+                        JOIN file_contents c ON c.id = f.content_id
+                        WHERE f.archived = 0 AND f.trash_id IS NULL
+                    ) AS logical_size,
+                    (SELECT COALESCE(SUM(size), 0) FROM file_contents) AS content_size,
+                    (
+                        SELECT COALESCE(SUM(size), 0) FROM file_contents
+                        WHERE chunked = 0 AND compressed = 0
+                    )
+                        + (SELECT COALESCE(SUM(size), 0) FROM content_frames)
+                        + (SELECT COALESCE(SUM(size), 0) FROM chunks) AS stored_size
+            "#,
+        )
+        .fetch_one(conn)
+        .await
+    }
+}
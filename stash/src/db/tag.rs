@@ -1,5 +1,5 @@
 use chrono::NaiveDateTime;
-use sqlx::{Executor, Sqlite, prelude::FromRow, query_as};
+use sqlx::{Executor, Sqlite, prelude::FromRow, query, query_as};
 
 #[allow(dead_code)]
 #[derive(Debug, FromRow)]
@@ -9,6 +9,13 @@ pub struct Tag {
     pub created: NaiveDateTime,
 }
 
+#[derive(Debug, FromRow)]
+pub struct TagStats {
+    pub name: String,
+    pub files: i64,
+    pub size: i64,
+}
+
 impl Tag {
     pub async fn all<'a, E: Executor<'a, Database = Sqlite>>(
         conn: E,
@@ -39,4 +46,57 @@ impl Tag {
         .fetch_one(conn)
         .await
     }
+
+    pub async fn rename<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        id: i64,
+        name: &str,
+    ) -> Result<u64, sqlx::Error> {
+        query("UPDATE tags SET name = $2 WHERE id = $1")
+            .bind(id)
+            .bind(name)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    pub async fn delete<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        id: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query("DELETE FROM tags WHERE id = $1")
+            .bind(id)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    /// Deletes tags that no file version refers to.
+    pub async fn prune<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+    ) -> Result<u64, sqlx::Error> {
+        query("DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM file_tags)")
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    pub async fn stats<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+    ) -> Result<Vec<TagStats>, sqlx::Error> {
+        query_as::<_, TagStats>(
+            r#"
+                SELECT t.name, COUNT(f.id) AS files, COALESCE(SUM(c.size), 0) AS size
+                FROM tags t
+                LEFT JOIN file_tags ft ON ft.tag_id = t.id
+                LEFT JOIN files f
+                    ON f.id = ft.file_id AND f.archived = 0 AND f.trash_id IS NULL
+                LEFT JOIN file_contents c ON c.id = f.content_id
+                GROUP BY t.id
+                ORDER BY t.name
+            "#,
+        )
+        .fetch_all(conn)
+        .await
+    }
 }
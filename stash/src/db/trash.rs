@@ -0,0 +1,91 @@
+use chrono::NaiveDateTime;
+use sqlx::{Executor, Sqlite, prelude::FromRow, query, query_as};
+
+use crate::SHA256;
+
+#[allow(dead_code)]
+#[derive(Debug, FromRow)]
+pub struct Trash {
+    pub id: i64,
+    pub name: String,
+    pub deleted_by: String,
+    pub deleted: NaiveDateTime,
+    pub keep: Option<i64>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct TrashDesc {
+    pub id: i64,
+    pub name: String,
+    pub size: i64,
+    pub hash: SHA256,
+    pub versions: i64,
+    pub deleted_by: String,
+    pub deleted: NaiveDateTime,
+}
+
+impl Trash {
+    pub async fn all<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+    ) -> Result<Vec<Trash>, sqlx::Error> {
+        query_as::<_, Trash>("SELECT * FROM trash ORDER BY id")
+            .fetch_all(conn)
+            .await
+    }
+
+    pub async fn by_id<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        id: i64,
+    ) -> Result<Option<Trash>, sqlx::Error> {
+        query_as::<_, Trash>("SELECT * FROM trash WHERE id = $1")
+            .bind(id)
+            .fetch_optional(conn)
+            .await
+    }
+
+    pub async fn list<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+    ) -> Result<Vec<TrashDesc>, sqlx::Error> {
+        query_as::<_, TrashDesc>(
+            r#"
+                SELECT
+                    t.id, t.name, c.size, c.hash,
+                    (SELECT COUNT(*) FROM files v WHERE v.trash_id = t.id) AS versions,
+                    t.deleted_by, t.deleted
+                FROM trash t
+                JOIN files f ON f.trash_id = t.id AND f.archived = 0
+                JOIN file_contents c ON c.id = f.content_id
+                ORDER BY t.id DESC
+            "#,
+        )
+        .fetch_all(conn)
+        .await
+    }
+
+    pub async fn insert<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+        deleted_by: &str,
+        keep: Option<i64>,
+    ) -> Result<Trash, sqlx::Error> {
+        query_as::<_, Trash>(
+            "INSERT INTO trash (name, deleted_by, deleted, keep) VALUES ($1, $2, datetime('now'), $3) RETURNING *",
+        )
+        .bind(name)
+        .bind(deleted_by)
+        .bind(keep)
+        .fetch_one(conn)
+        .await
+    }
+
+    pub async fn delete<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        id: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query("DELETE FROM trash WHERE id = $1")
+            .bind(id)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+}
@@ -0,0 +1,63 @@
+use sqlx::{Executor, Sqlite, prelude::FromRow, query, query_as};
+
+#[allow(dead_code)]
+#[derive(Debug, FromRow)]
+pub struct VersionLimit {
+    pub id: i64,
+    pub name: String,
+    pub keep: i64,
+}
+
+impl VersionLimit {
+    pub async fn by_name<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+    ) -> Result<Option<VersionLimit>, sqlx::Error> {
+        query_as::<_, VersionLimit>("SELECT * FROM version_limits WHERE name = $1")
+            .bind(name)
+            .fetch_optional(conn)
+            .await
+    }
+
+    pub async fn set<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+        keep: i64,
+    ) -> Result<u64, sqlx::Error> {
+        query(
+            r#"
+                INSERT INTO version_limits (name, keep) VALUES ($1, $2)
+                ON CONFLICT (name) DO UPDATE SET keep = excluded.keep
+            "#,
+        )
+        .bind(name)
+        .bind(keep)
+        .execute(conn)
+        .await
+        .map(|r| r.rows_affected())
+    }
+
+    pub async fn rename<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        from: &str,
+        to: &str,
+    ) -> Result<u64, sqlx::Error> {
+        query("UPDATE version_limits SET name = $2 WHERE name = $1")
+            .bind(from)
+            .bind(to)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+
+    pub async fn delete<'a, E: Executor<'a, Database = Sqlite>>(
+        conn: E,
+        name: &str,
+    ) -> Result<u64, sqlx::Error> {
+        query("DELETE FROM version_limits WHERE name = $1")
+            .bind(name)
+            .execute(conn)
+            .await
+            .map(|r| r.rows_affected())
+    }
+}
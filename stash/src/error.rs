@@ -1,15 +1,28 @@
+use crate::common::Failure;
+
 #[derive(Debug)]
 pub enum Error {
     ConnectionError(iroh::endpoint::ConnectionError),
     ConnectError(iroh::endpoint::ConnectError),
     CloseError(iroh::endpoint::ClosedStream),
     ReadError(iroh::endpoint::ReadError),
+    ReadExactError(iroh::endpoint::ReadExactError),
     WriteError(iroh::endpoint::WriteError),
     KeyParsingError(iroh::KeyParsingError),
     DecodeError(bincode::error::DecodeError),
     EncodeError(bincode::error::EncodeError),
     IoError(std::io::Error),
     DbError(sqlx::Error),
+    StorageError(String),
+    NotFound(String),
+    Conflict(String),
+    InvalidArgument(String),
+    OutOfRange(String),
+    Unauthorized(String),
+    Quota(String),
+    Internal(String),
+    IncompatibleVersion { client: u32, server: u32 },
+    Unsupported(String),
 }
 
 impl std::fmt::Display for Error {
@@ -19,12 +32,26 @@ impl std::fmt::Display for Error {
             Self::ConnectError(e) => write!(f, "ConnectError: {:?}", e),
             Self::CloseError(e) => write!(f, "CloseError: {:?}", e),
             Self::ReadError(e) => write!(f, "ReadError: {:?}", e),
+            Self::ReadExactError(e) => write!(f, "ReadExactError: {:?}", e),
             Self::WriteError(e) => write!(f, "WriteError: {:?}", e),
             Self::KeyParsingError(e) => write!(f, "KeyParsingError: {:?}", e),
             Self::DecodeError(e) => write!(f, "DecodeError: {:?}", e),
             Self::EncodeError(e) => write!(f, "EncodeError: {:?}", e),
             Self::IoError(e) => write!(f, "IoError: {:?}", e),
             Self::DbError(e) => write!(f, "DbError: {:?}", e),
+            Self::StorageError(e) => write!(f, "StorageError: {e}"),
+            Self::NotFound(e) => write!(f, "NotFound: {e}"),
+            Self::Conflict(e) => write!(f, "Conflict: {e}"),
+            Self::InvalidArgument(e) => write!(f, "InvalidArgument: {e}"),
+            Self::OutOfRange(e) => write!(f, "OutOfRange: {e}"),
+            Self::Unauthorized(e) => write!(f, "Unauthorized: {e}"),
+            Self::Quota(e) => write!(f, "Quota: {e}"),
+            Self::Internal(e) => write!(f, "Internal: {e}"),
+            Self::IncompatibleVersion { client, server } => write!(
+                f,
+                "IncompatibleVersion: client speaks protocol {client}, server speaks {server}"
+            ),
+            Self::Unsupported(c) => write!(f, "Unsupported: server lacks capability {c}"),
         }
     }
 }
@@ -55,6 +82,12 @@ impl From<iroh::endpoint::ReadError> for Error {
     }
 }
 
+impl From<iroh::endpoint::ReadExactError> for Error {
+    fn from(value: iroh::endpoint::ReadExactError) -> Self {
+        Self::ReadExactError(value)
+    }
+}
+
 impl From<iroh::endpoint::WriteError> for Error {
     fn from(value: iroh::endpoint::WriteError) -> Self {
         Self::WriteError(value)
@@ -90,3 +123,17 @@ impl From<sqlx::Error> for Error {
         Self::DbError(value)
     }
 }
+
+impl From<Failure> for Error {
+    fn from(value: Failure) -> Self {
+        match value {
+            Failure::NotFound(e) => Self::NotFound(e),
+            Failure::Conflict(e) => Self::Conflict(e),
+            Failure::InvalidArgument(e) => Self::InvalidArgument(e),
+            Failure::OutOfRange(e) => Self::OutOfRange(e),
+            Failure::Unauthorized(e) => Self::Unauthorized(e),
+            Failure::Quota(e) => Self::Quota(e),
+            Failure::Internal(e) => Self::Internal(e),
+        }
+    }
+}
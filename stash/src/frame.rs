@@ -0,0 +1,39 @@
+use bincode::{Decode, Encode};
+use iroh::endpoint::{RecvStream, SendStream};
+
+use crate::Error;
+
+pub async fn write<T: Encode>(
+    tx: &mut SendStream,
+    msg: &T,
+    bincode_config: bincode::config::Configuration,
+) -> Result<(), Error> {
+    let data = bincode::encode_to_vec(msg, bincode_config)?;
+    let len = data.len() as u32;
+
+    tx.write_all(&len.to_be_bytes()).await?;
+    tx.write_all(&data).await?;
+    Ok(())
+}
+
+pub async fn read<T: Decode<()>>(
+    rx: &mut RecvStream,
+    bincode_config: bincode::config::Configuration,
+    limit: usize,
+) -> Result<T, Error> {
+    let mut len = [0u8; 4];
+    rx.read_exact(&mut len).await?;
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > limit {
+        return Err(Error::Quota(format!(
+            "Message of {len} bytes exceeds limit of {limit}"
+        )));
+    }
+
+    let mut data = vec![0; len];
+    rx.read_exact(&mut data).await?;
+
+    let msg = bincode::decode_from_slice(&data, bincode_config)?.0;
+    Ok(msg)
+}
@@ -1,11 +1,29 @@
+mod blocks;
+mod chunks;
 mod client;
 mod common;
+mod compression;
 mod db;
 mod error;
+mod frame;
+mod limits;
+mod locks;
 mod server;
 mod sha256;
+mod storage;
 
-pub use client::Client;
-pub use common::{ALPN, Blob, Cmd, File, FileDescription, Response, SHA256, Tag};
+pub use client::{Client, Download};
+pub use common::{
+    ALPN, Blob, BlobId, BlobInfo, CAPABILITIES, Cmd, ContentHash, Failure, File, FileDescription,
+    FileSelection, FileVersion, GcReport, Hello, PROTOCOL_VERSION, Response, RotationReport,
+    SHA256, ScrubReport, ServerInfo, Stats, Tag, TagInfo, TrashEntry,
+};
+pub use compression::CompressionPolicy;
 pub use error::Error;
+pub use limits::Limits;
 pub use server::{NodeAuth, Server};
+pub use sha256::{Hasher, HashingReader};
+pub use storage::{
+    EncryptedStorage, LocalStorage, MasterKey, ObjectMeta, ObjectReader, S3Config, S3Storage,
+    Storage,
+};
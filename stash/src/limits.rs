@@ -0,0 +1,20 @@
+#[derive(Clone, Debug)]
+pub struct Limits {
+    pub max_request_size: usize,
+    pub max_download_len: u64,
+    pub max_append_size: u64,
+    pub max_blob_size: u64,
+    pub max_concurrent_requests: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_size: 16_000_000,
+            max_download_len: 16_000_000,
+            max_append_size: 10_000_000,
+            max_blob_size: 100_000_000_000,
+            max_concurrent_requests: 64,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::OwnedMutexGuard;
+
+type LockMap = Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>;
+
+/// Async locks on individual storage keys, so that work on one blob or
+/// content object does not wait for work on others. Entries are dropped
+/// once nobody holds or waits for them.
+#[derive(Clone, Default)]
+pub(crate) struct KeyLocks {
+    locks: LockMap,
+}
+
+pub(crate) struct KeyGuard {
+    locks: LockMap,
+    key: String,
+    lock: Arc<tokio::sync::Mutex<()>>,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl KeyLocks {
+    pub(crate) async fn lock(&self, key: &str) -> KeyGuard {
+        let mut guard = self.guard(key);
+        guard.guard = Some(guard.lock.clone().lock_owned().await);
+        guard
+    }
+
+    /// Locks `key` unless someone else holds it.
+    pub(crate) fn try_lock(&self, key: &str) -> Option<KeyGuard> {
+        let mut guard = self.guard(key);
+        guard.guard = Some(guard.lock.clone().try_lock_owned().ok()?);
+        Some(guard)
+    }
+
+    fn guard(&self, key: &str) -> KeyGuard {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+
+        KeyGuard {
+            locks: self.locks.clone(),
+            key: key.to_string(),
+            lock,
+            guard: None,
+        }
+    }
+}
+
+impl Drop for KeyGuard {
+    fn drop(&mut self) {
+        self.guard.take();
+
+        // Only the map and this guard are left if nobody else is waiting
+        let mut locks = self.locks.lock().unwrap();
+        if Arc::strong_count(&self.lock) == 2 {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+/// Counts operations using keys that several of them can share at once,
+/// such as chunks, so that deletes can leave those keys alone.
+#[derive(Clone, Default)]
+pub(crate) struct KeyPins {
+    pins: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+pub(crate) struct PinGuard {
+    pins: Arc<Mutex<HashMap<String, usize>>>,
+    key: String,
+}
+
+impl KeyPins {
+    pub(crate) fn pin(&self, key: &str) -> PinGuard {
+        *self
+            .pins
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default() += 1;
+
+        PinGuard {
+            pins: self.pins.clone(),
+            key: key.to_string(),
+        }
+    }
+
+    pub(crate) fn pinned(&self, key: &str) -> bool {
+        self.pins.lock().unwrap().contains_key(key)
+    }
+}
+
+impl Drop for PinGuard {
+    fn drop(&mut self) {
+        let mut pins = self.pins.lock().unwrap();
+        if let Some(n) = pins.get_mut(&self.key) {
+            *n -= 1;
+            if *n == 0 {
+                pins.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyLocks, KeyPins};
+
+    #[tokio::test]
+    async fn key_locks() {
+        let locks = KeyLocks::default();
+
+        let a = locks.lock("blobs/a").await;
+        assert!(locks.try_lock("blobs/a").is_none());
+        let b = locks.try_lock("blobs/b").unwrap();
+        assert_eq!(locks.locks.lock().unwrap().len(), 2);
+
+        drop(a);
+        drop(b);
+        assert!(locks.locks.lock().unwrap().is_empty());
+        assert!(locks.try_lock("blobs/a").is_some());
+    }
+
+    #[test]
+    fn key_pins() {
+        let pins = KeyPins::default();
+
+        let a = pins.pin("chunks/a");
+        let b = pins.pin("chunks/a");
+        assert!(pins.pinned("chunks/a"));
+        assert!(!pins.pinned("chunks/b"));
+
+        drop(a);
+        assert!(pins.pinned("chunks/a"));
+        drop(b);
+        assert!(!pins.pinned("chunks/a"));
+    }
+}
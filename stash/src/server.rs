@@ -1,32 +1,115 @@
-use std::{fmt::Debug, io::SeekFrom, os::unix::fs::MetadataExt, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use iroh::{
     NodeId,
-    endpoint::Connection,
+    endpoint::{Connection, RecvStream, SendStream},
     protocol::{AcceptError, ProtocolHandler},
 };
 use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
-use uuid::Uuid;
-
-use super::{Blob, Cmd, Error, File, FileDescription, Response, SHA256, Tag, db, sha256};
+use tokio::io::AsyncReadExt;
+
+use super::{
+    Blob, BlobId, BlobInfo, Cmd, ContentHash, Error, Failure, File, FileDescription, FileSelection,
+    FileVersion, GcReport, Hello, Limits, PROTOCOL_VERSION, Response, RotationReport, ScrubReport,
+    ServerInfo, Stats, Tag, TagInfo, TrashEntry,
+    chunks::{ChunkReader, Chunker},
+    compression::{self, CompressionPolicy},
+    db, frame,
+    locks::{KeyLocks, KeyPins, PinGuard},
+    sha256,
+    storage::{LocalStorage, ObjectReader, Storage},
+};
 
-const BLOB_DIR: &'static str = "blobs";
-const FILE_DIR: &'static str = "files";
+const BLOB_DIR: &str = "blobs";
+const FILE_DIR: &str = "files";
+const CHUNK_DIR: &str = "chunks";
+const QUARANTINE_DIR: &str = "quarantine";
+const MAX_HELLO_SIZE: usize = 1_000;
+const MAX_SCRUB_BATCH: u32 = 10_000;
+const DEFAULT_BLOB_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_MAX_VERSIONS: u32 = 10;
+const DEFAULT_TRASH_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
 
 pub trait NodeAuth {
     fn allow(&self, node: NodeId) -> impl Future<Output = bool> + Send;
+
+    fn admin(&self, _node: NodeId) -> impl Future<Output = bool> + Send {
+        async { false }
+    }
 }
 
-#[derive(Clone)]
-pub struct Server<A: NodeAuth> {
-    auth: A,
+pub struct Server<A: NodeAuth, S: Storage = LocalStorage> {
+    auth: Arc<A>,
     root: PathBuf,
+    storage: Arc<S>,
     db: SqlitePool,
+    limits: Limits,
+    blob_ttl: Duration,
+    chunking: bool,
+    compression: Option<CompressionPolicy>,
+    max_versions: u32,
+    trash_retention: Duration,
+    requests: Arc<Mutex<HashMap<NodeId, usize>>>,
+    content_lock: Arc<tokio::sync::Mutex<()>>,
+    key_locks: KeyLocks,
+    key_pins: KeyPins,
     bincode_config: bincode::config::Configuration,
 }
 
-impl<A: NodeAuth> Debug for Server<A> {
+impl<A: NodeAuth, S: Storage> Clone for Server<A, S> {
+    fn clone(&self) -> Self {
+        Self {
+            auth: self.auth.clone(),
+            root: self.root.clone(),
+            storage: self.storage.clone(),
+            db: self.db.clone(),
+            limits: self.limits.clone(),
+            blob_ttl: self.blob_ttl,
+            chunking: self.chunking,
+            compression: self.compression.clone(),
+            max_versions: self.max_versions,
+            trash_retention: self.trash_retention,
+            requests: self.requests.clone(),
+            content_lock: self.content_lock.clone(),
+            key_locks: self.key_locks.clone(),
+            key_pins: self.key_pins.clone(),
+            bincode_config: self.bincode_config,
+        }
+    }
+}
+
+/// How the bytes of newly committed content are laid out in storage.
+enum Layout {
+    Whole,
+    Chunked(Vec<(ContentHash, u64)>),
+    Compressed(Vec<(u64, u64)>),
+}
+
+struct RequestGuard {
+    requests: Arc<Mutex<HashMap<NodeId, usize>>>,
+    node_id: NodeId,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        let mut requests = self.requests.lock().unwrap();
+        if let Some(n) = requests.get_mut(&self.node_id) {
+            *n -= 1;
+            if *n == 0 {
+                requests.remove(&self.node_id);
+            }
+        }
+    }
+}
+
+impl<A: NodeAuth, S: Storage> Debug for Server<A, S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Server {{ root: {:?}, db: {:?} }}", self.root, self.db)?;
         Ok(())
@@ -35,20 +118,152 @@ impl<A: NodeAuth> Debug for Server<A> {
 
 impl<A: NodeAuth> Server<A> {
     pub async fn new(auth: A, root: PathBuf) -> Result<Self, Error> {
+        let storage = LocalStorage::open(root.canonicalize()?).await?;
+        Self::with_storage(auth, root, storage).await
+    }
+}
+
+impl<A: NodeAuth, S: Storage> Server<A, S> {
+    pub async fn with_storage(auth: A, root: PathBuf, storage: S) -> Result<Self, Error> {
         let db = root.join("server.db");
         let db = setup_db(db.to_str().unwrap()).await?;
 
         let i = Self {
-            auth,
+            auth: Arc::new(auth),
             root: root.canonicalize()?,
+            storage: Arc::new(storage),
             db,
+            limits: Limits::default(),
+            blob_ttl: DEFAULT_BLOB_TTL,
+            chunking: false,
+            compression: None,
+            max_versions: DEFAULT_MAX_VERSIONS,
+            trash_retention: DEFAULT_TRASH_RETENTION,
+            requests: Arc::new(Mutex::new(HashMap::new())),
+            content_lock: Arc::new(tokio::sync::Mutex::new(())),
+            key_locks: KeyLocks::default(),
+            key_pins: KeyPins::default(),
             bincode_config: bincode::config::standard(),
         };
 
+        i.recover().await?;
         Ok(i)
     }
 
-    async fn handle(&self, caller: NodeId, cmd: Cmd) -> Result<Vec<u8>, Error> {
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_blob_ttl(mut self, ttl: Duration) -> Self {
+        self.blob_ttl = ttl;
+        self
+    }
+
+    pub fn with_chunking(mut self, chunking: bool) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// Compresses newly committed content that matches `policy`. Chunked
+    /// storage takes precedence.
+    pub fn with_compression(mut self, policy: CompressionPolicy) -> Self {
+        self.compression = Some(policy);
+        self
+    }
+
+    /// Keeps at most `keep` versions of each file, counting the current one,
+    /// unless a file sets its own limit.
+    pub fn with_max_versions(mut self, keep: u32) -> Self {
+        self.max_versions = keep.max(1);
+        self
+    }
+
+    /// Keeps deleted files in the trash for `retention` before
+    /// `expire_trash` reclaims them.
+    pub fn with_trash_retention(mut self, retention: Duration) -> Self {
+        self.trash_retention = retention;
+        self
+    }
+
+    async fn serve(&self, node_id: NodeId, mut tx: SendStream, mut rx: RecvStream) {
+        let _guard = match self.acquire(node_id) {
+            Some(guard) => guard,
+            None => {
+                let msg = format!(
+                    "More than {} concurrent requests",
+                    self.limits.max_concurrent_requests
+                );
+                tracing::warn!(node_id = ?node_id, "too_many_requests");
+                rx.stop(0u32.into()).ok();
+                self.reject(&mut tx, Failure::Quota(msg)).await;
+                return;
+            }
+        };
+
+        let limit = self.limits.max_request_size;
+        let cmd: Cmd = match frame::read(&mut rx, self.bincode_config, limit).await {
+            Ok(cmd) => cmd,
+            Err(Error::Quota(msg)) => {
+                tracing::warn!(node_id = ?node_id, msg, "request_too_large");
+                rx.stop(0u32.into()).ok();
+                self.reject(&mut tx, Failure::Quota(msg)).await;
+                return;
+            }
+            Err(e) => {
+                tracing::warn!(node_id = ?node_id, err = ?e, "invalid_request");
+                tx.reset(1u32.into()).ok();
+                return;
+            }
+        };
+
+        let rsp = self.handle(node_id, cmd.clone(), &mut tx, &mut rx).await;
+        if rsp.is_err() {
+            tracing::warn!(cmd = ?cmd, rsp = ?rsp, "handle_failed");
+
+            // Streamed responses may already be partially written
+            if matches!(cmd, Cmd::Stream { .. }) {
+                tx.reset(1u32.into()).ok();
+                return;
+            }
+
+            self.reject(&mut tx, internal()).await;
+            return;
+        }
+
+        tx.finish().ok();
+    }
+
+    async fn reject(&self, tx: &mut SendStream, failure: Failure) {
+        let rsp: Response<()> = Response::Err(failure);
+        if let Ok(rsp) = bincode::encode_to_vec(&rsp, self.bincode_config) {
+            tx.write_all(&rsp).await.ok();
+        }
+
+        tx.finish().ok();
+    }
+
+    fn acquire(&self, node_id: NodeId) -> Option<RequestGuard> {
+        let mut requests = self.requests.lock().unwrap();
+        let n = requests.entry(node_id).or_insert(0);
+        if *n >= self.limits.max_concurrent_requests {
+            return None;
+        }
+
+        *n += 1;
+        Some(RequestGuard {
+            requests: self.requests.clone(),
+            node_id,
+        })
+    }
+
+    async fn handle(
+        &self,
+        caller: NodeId,
+        cmd: Cmd,
+        tx: &mut SendStream,
+        rx: &mut RecvStream,
+    ) -> Result<(), Error> {
         tracing::info!(cmd = ?cmd, "handle");
 
         let json = match cmd {
@@ -57,15 +272,15 @@ impl<A: NodeAuth> Server<A> {
                 bincode::encode_to_vec(&tags, self.bincode_config)?
             }
             Cmd::CreateBlob => {
-                let blob = self.create_blob().await?;
+                let blob = self.create_blob(caller).await?;
                 bincode::encode_to_vec(&blob, self.bincode_config)?
             }
             Cmd::DescribeBlob { name } => {
-                let blob = self.describe_blob(name).await?;
+                let blob = self.describe_blob(caller, name).await?;
                 bincode::encode_to_vec(&blob, self.bincode_config)?
             }
-            Cmd::AppendBlob { name, data } => {
-                let blob = self.append_blob(name, data).await?;
+            Cmd::AppendBlob { name, offset, data } => {
+                let blob = self.append_blob(caller, name, offset, data).await?;
                 bincode::encode_to_vec(&blob, self.bincode_config)?
             }
             Cmd::CommitBlob {
@@ -73,15 +288,21 @@ impl<A: NodeAuth> Server<A> {
                 file_name,
                 tags,
                 replace,
+                hash,
+                size,
             } => {
                 let file = self
-                    .commit_blob(caller, name, file_name, tags, replace)
+                    .commit_blob(caller, name, file_name, tags, replace, hash, size)
                     .await?;
 
                 bincode::encode_to_vec(&file, self.bincode_config)?
             }
+            Cmd::ListBlobs => {
+                let blobs = self.list_blobs(caller).await?;
+                bincode::encode_to_vec(&blobs, self.bincode_config)?
+            }
             Cmd::GcBlobs => {
-                let rsp = self.gc_blobs().await?;
+                let rsp = Response::Ok(self.gc_blobs().await?);
                 bincode::encode_to_vec(&rsp, self.bincode_config)?
             }
             Cmd::List { tag, prefix } => {
@@ -97,16 +318,119 @@ impl<A: NodeAuth> Server<A> {
                 bincode::encode_to_vec(&tags, self.bincode_config)?
             }
             Cmd::Delete { name } => {
-                let rsp = self.delete(name).await?;
+                let rsp = self.delete(caller, name).await?;
                 bincode::encode_to_vec(&rsp, self.bincode_config)?
             }
             Cmd::Download { hash, start, len } => {
                 let data = self.download(hash, start, len).await?;
                 bincode::encode_to_vec(&data, self.bincode_config)?
             }
+            Cmd::Stream { hash, start, len } => return self.stream(tx, hash, start, len).await,
+            Cmd::Upload {
+                file_name,
+                tags,
+                replace,
+                size,
+                hash,
+            } => {
+                let file = self
+                    .upload(caller, rx, file_name, tags, replace, size, hash)
+                    .await?;
+
+                bincode::encode_to_vec(&file, self.bincode_config)?
+            }
+            Cmd::HasContent { hash } => {
+                let rsp = self.has_content(hash).await?;
+                bincode::encode_to_vec(&rsp, self.bincode_config)?
+            }
+            Cmd::CommitHash {
+                hash,
+                file_name,
+                tags,
+                replace,
+            } => {
+                let file = self
+                    .commit_hash(caller, hash, file_name, tags, replace)
+                    .await?;
+
+                bincode::encode_to_vec(&file, self.bincode_config)?
+            }
+            Cmd::Scrub {
+                after,
+                limit,
+                quarantine,
+            } => {
+                let report = self.scrub(after, limit, quarantine).await?;
+                bincode::encode_to_vec(&report, self.bincode_config)?
+            }
+            Cmd::Stats => {
+                let stats = self.stats().await?;
+                bincode::encode_to_vec(&stats, self.bincode_config)?
+            }
+            Cmd::WriteBlob { name, offset, data } => {
+                let blob = self.write_blob(caller, name, offset, data).await?;
+                bincode::encode_to_vec(&blob, self.bincode_config)?
+            }
+            Cmd::RotateKeys => {
+                let report = self.rotate_keys(caller).await?;
+                bincode::encode_to_vec(&report, self.bincode_config)?
+            }
+            Cmd::Versions { name } => {
+                let versions = self.versions(name).await?;
+                bincode::encode_to_vec(&versions, self.bincode_config)?
+            }
+            Cmd::RestoreVersion { name, version } => {
+                let file = self.restore_version(caller, name, version).await?;
+                bincode::encode_to_vec(&file, self.bincode_config)?
+            }
+            Cmd::SetVersionLimit { name, keep } => {
+                let rsp = self.set_version_limit(name, keep).await?;
+                bincode::encode_to_vec(&rsp, self.bincode_config)?
+            }
+            Cmd::Trash => {
+                let entries = self.trash().await?;
+                bincode::encode_to_vec(&entries, self.bincode_config)?
+            }
+            Cmd::RestoreTrash { id, name } => {
+                let file = self.restore_trash(id, name).await?;
+                bincode::encode_to_vec(&file, self.bincode_config)?
+            }
+            Cmd::PurgeTrash { id } => {
+                let rsp = self.purge_trash(id).await?;
+                bincode::encode_to_vec(&rsp, self.bincode_config)?
+            }
+            Cmd::Rename { from, to, replace } => {
+                let file = self.rename(caller, from, to, replace).await?;
+                bincode::encode_to_vec(&file, self.bincode_config)?
+            }
+            Cmd::Copy { from, to, tags } => {
+                let file = self.copy(caller, from, to, tags).await?;
+                bincode::encode_to_vec(&file, self.bincode_config)?
+            }
+            Cmd::EditTags { files, add, remove } => {
+                let files = self.edit_tags(files, add, remove).await?;
+                bincode::encode_to_vec(&files, self.bincode_config)?
+            }
+            Cmd::RenameTag { from, to } => {
+                let rsp = self.rename_tag(from, to).await?;
+                bincode::encode_to_vec(&rsp, self.bincode_config)?
+            }
+            Cmd::MergeTags { from, into } => {
+                let rsp = self.merge_tags(from, into).await?;
+                bincode::encode_to_vec(&rsp, self.bincode_config)?
+            }
+            Cmd::DeleteTag { name, force } => {
+                let rsp = self.delete_tag(name, force).await?;
+                bincode::encode_to_vec(&rsp, self.bincode_config)?
+            }
+            Cmd::TagStats => {
+                let stats = self.tag_stats().await?;
+                bincode::encode_to_vec(&stats, self.bincode_config)?
+            }
         };
 
-        Ok(json)
+        tx.write_all(&json).await?;
+        Ok(())
     }
 
     async fn tags(&self) -> Result<Response<Vec<String>>, Error> {
@@ -120,292 +444,1876 @@ impl<A: NodeAuth> Server<A> {
         Ok(rsp)
     }
 
-    async fn create_blob(&self) -> Result<Response<Blob>, Error> {
-        let name = Uuid::new_v4().to_string();
-        let path = self.blob_path(&name)?;
-
-        tokio::fs::File::create(&path).await?;
+    async fn tag_stats(&self) -> Result<Response<Vec<TagInfo>>, Error> {
+        let stats = db::Tag::stats(&self.db)
+            .await?
+            .into_iter()
+            .map(From::from)
+            .collect();
 
-        self.describe_blob(name).await
+        Ok(Response::Ok(stats))
     }
 
-    async fn describe_blob(&self, name: String) -> Result<Response<Blob>, Error> {
-        let path = self.blob_path(&name)?;
-        if !std::fs::exists(&path)? {
-            return Ok(Response::Err("No such blob".to_string()));
+    async fn rename_tag(&self, from: String, to: String) -> Result<Response<String>, Error> {
+        if Tag::from_str(&to).is_err() {
+            return Ok(Response::invalid_argument(format!("Invalid tag {to}")));
         }
 
-        let meta = tokio::fs::metadata(&path).await?;
+        let mut transaction = self.begin().await?;
 
-        let blob = Blob {
-            name,
-            size: meta.size(),
+        let Some(tag) = db::Tag::by_name(&mut *transaction, &from).await? else {
+            return Ok(Response::not_found("No such tag"));
         };
 
-        Ok(Response::Ok(blob))
-    }
-
-    async fn append_blob(&self, name: String, data: Vec<u8>) -> Result<Response<Blob>, Error> {
-        let path = self.blob_path(&name)?;
-        if !std::fs::exists(&path)? {
-            return Ok(Response::Err("No such blob".to_string()));
+        if db::Tag::by_name(&mut *transaction, &to).await?.is_some() {
+            return Ok(Response::conflict("Tag already exists"));
         }
 
-        let mut file = tokio::fs::File::options().append(true).open(&path).await?;
-        file.write_all(&data).await?;
-        file.flush().await?;
+        db::Tag::rename(&mut *transaction, tag.id, &to).await?;
+        transaction.commit().await?;
 
-        self.describe_blob(name).await
+        Ok(Response::ok())
     }
 
-    async fn commit_blob(
-        &self,
-        caller: NodeId,
-        name: String,
-        file_name: String,
-        tags: Vec<String>,
-        replace: bool,
-    ) -> Result<Response<File>, Error> {
-        if tags.is_empty() {
-            return Ok(Response::Err(format!("At least one tag is required")));
-        }
-
-        for tag in tags.iter() {
-            if Tag::from_str(&tag).is_err() {
-                return Ok(Response::Err(format!("Invalid tag {tag}")));
-            }
-        }
-
-        let existing_file = db::File::by_name(&self.db, &file_name).await?;
-        if !replace && existing_file.is_some() {
-            return Ok(Response::Err(format!("File already exists")));
-        }
-
-        let blob_path = self.blob_path(&name)?;
-        if !std::fs::exists(&blob_path)? {
-            return Ok(Response::Err("No such blob".to_string()));
+    async fn merge_tags(&self, from: String, into: String) -> Result<Response<String>, Error> {
+        if from == into {
+            return Ok(Response::invalid_argument("Cannot merge a tag into itself"));
         }
 
-        let meta = tokio::fs::metadata(&blob_path).await?;
-        let hash = sha256::digest(&blob_path).await?;
-        let file_path = self.file_path(&hash)?;
-        let node = format!("{caller}");
-
-        let mut transaction = self.db.begin().await?;
+        let mut transaction = self.begin().await?;
 
-        if let Some(existing_file) = existing_file.as_ref() {
-            db::File::delete(&mut *transaction, existing_file.id).await?;
-        }
+        let Some(source) = db::Tag::by_name(&mut *transaction, &from).await? else {
+            return Ok(Response::not_found("No such tag"));
+        };
 
-        let content = match db::FileContent::by_hash(&mut *transaction, &hash).await? {
-            Some(content) => content,
-            None => {
-                db::FileContent::insert(&mut *transaction, meta.size() as i64, &hash, &node).await?
-            }
+        let Some(target) = db::Tag::by_name(&mut *transaction, &into).await? else {
+            return Ok(Response::not_found("No such tag"));
         };
-        let file = db::File::insert(&mut *transaction, &file_name, content.id, &node).await?;
 
-        for tag in tags.iter() {
-            let tag = match db::Tag::by_name(&mut *transaction, tag).await? {
-                Some(tag) => tag,
-                None => db::Tag::insert(&mut *transaction, tag).await?,
-            };
+        db::FileTag::merge(&mut *transaction, source.id, target.id).await?;
+        db::Tag::delete(&mut *transaction, source.id).await?;
+        transaction.commit().await?;
 
-            db::FileTag::insert(&mut *transaction, file.id, tag.id).await?;
-        }
+        Ok(Response::ok())
+    }
 
-        let file = File {
-            name: file_name,
-            size: meta.size(),
-            hash,
-            created: file.created.and_utc().timestamp(),
+    async fn delete_tag(&self, name: String, force: bool) -> Result<Response<String>, Error> {
+        let mut transaction = self.begin().await?;
+
+        let Some(tag) = db::Tag::by_name(&mut *transaction, &name).await? else {
+            return Ok(Response::not_found("No such tag"));
         };
 
-        tokio::fs::rename(&blob_path, &file_path).await?;
-        if let Some(existing_file) = existing_file {
-            self.gc_content(&mut transaction, existing_file.content_id)
-                .await?;
+        let untagged = db::FileTag::sole(&mut *transaction, tag.id).await?;
+        if untagged > 0 && !force {
+            return Ok(Response::conflict(format!(
+                "{untagged} files would be left without tags"
+            )));
         }
 
+        db::Tag::delete(&mut *transaction, tag.id).await?;
         transaction.commit().await?;
-        Ok(Response::Ok(file))
-    }
-
-    async fn gc_blobs(&self) -> Result<Response<String>, Error> {
-        let blobs_path = self.root.join(BLOB_DIR);
-
-        let mut dir = tokio::fs::read_dir(blobs_path).await?;
-        while let Some(entry) = dir.next_entry().await? {
-            tokio::fs::remove_file(entry.path()).await?;
-        }
 
         Ok(Response::ok())
     }
 
-    async fn list(
-        &self,
-        tag: String,
-        prefix: Option<String>,
-    ) -> Result<Response<Vec<File>>, Error> {
-        if Tag::from_str(&tag).is_err() {
-            return Ok(Response::Err(format!("Invalid tag {tag}")));
-        }
-
-        let term = prefix.as_ref().map(|s| s.as_str()).unwrap_or("");
-        let term = format!("{term}%");
+    async fn create_blob(&self, caller: NodeId) -> Result<Response<Blob>, Error> {
+        let name = BlobId::new();
 
-        let files = db::File::search(&self.db, &tag, &term)
-            .await?
-            .into_iter()
-            .map(From::from)
-            .collect();
+        db::Blob::insert(&self.db, &name.to_string(), &format!("{caller}")).await?;
+        self.storage
+            .put(&blob_key(&name), &mut tokio::io::empty(), 0)
+            .await?;
 
-        let rsp = Response::Ok(files);
-        Ok(rsp)
+        self.describe_blob(caller, name).await
     }
 
-    async fn search(&self, tag: String, term: String) -> Result<Response<Vec<File>>, Error> {
-        if Tag::from_str(&tag).is_err() {
-            return Ok(Response::Err(format!("Invalid tag {tag}")));
-        }
+    async fn describe_blob(&self, caller: NodeId, name: BlobId) -> Result<Response<Blob>, Error> {
+        let key = match self.owned_blob(caller, &name).await? {
+            Response::Ok(key) => key,
+            Response::Err(e) => return Ok(Response::Err(e)),
+        };
 
-        let term = format!("%{term}%");
+        let Some(meta) = self.storage.stat(&key).await? else {
+            return Ok(Response::not_found("No such blob"));
+        };
 
-        let files = db::File::search(&self.db, &tag, &term)
-            .await?
-            .into_iter()
-            .map(From::from)
-            .collect();
+        let blob = Blob {
+            name,
+            size: meta.size,
+        };
 
-        let rsp = Response::Ok(files);
-        Ok(rsp)
+        Ok(Response::Ok(blob))
     }
 
-    async fn describe(&self, name: String) -> Result<Response<FileDescription>, Error> {
-        match db::File::by_name(&self.db, &name).await? {
-            None => Ok(Response::Err("No such file".to_string())),
-            Some(file) => {
-                let tags = db::FileTag::for_file(&self.db, file.id).await?;
-                let desc = FileDescription::new(file, tags);
-                Ok(Response::Ok(desc))
-            }
+    async fn append_blob(
+        &self,
+        caller: NodeId,
+        name: BlobId,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Response<Blob>, Error> {
+        let key = match self.owned_blob(caller, &name).await? {
+            Response::Ok(key) => key,
+            Response::Err(e) => return Ok(Response::Err(e)),
+        };
+
+        let len = data.len() as u64;
+        if len > self.limits.max_append_size {
+            return Ok(Response::quota(format!(
+                "Append of {len} bytes exceeds limit of {}",
+                self.limits.max_append_size
+            )));
         }
-    }
 
-    async fn delete(&self, name: String) -> Result<Response<String>, Error> {
-        match db::File::by_name(&self.db, &name).await? {
-            None => Ok(Response::Err("No such file".to_string())),
-            Some(file) => {
-                let mut transaction = self.db.begin().await?;
-                db::File::delete(&mut *transaction, file.id).await?;
-                self.gc_content(&mut transaction, file.content_id).await?;
-                transaction.commit().await?;
+        let _blob_lock = self.key_locks.lock(&key).await;
 
-                Ok(Response::ok())
-            }
+        let Some(meta) = self.storage.stat(&key).await? else {
+            return Ok(Response::not_found("No such blob"));
+        };
+
+        if meta.size != offset {
+            return Ok(Response::conflict(format!(
+                "Blob is at offset {}, not {offset}",
+                meta.size
+            )));
         }
-    }
 
-    async fn gc_content(
-        &self,
-        transaction: &mut sqlx::Transaction<'static, sqlx::Sqlite>,
-        content_id: i64,
-    ) -> Result<(), Error> {
-        if let Some(content) =
-            db::FileContent::find_orphaned(&mut **transaction, content_id).await?
-        {
-            let path = self.file_path(&content.hash)?;
-            tokio::fs::remove_file(path).await?;
-            db::FileContent::delete(&mut **transaction, content.id).await?;
+        if meta.size + len > self.limits.max_blob_size {
+            return Ok(Response::quota(format!(
+                "Blob size would exceed limit of {}",
+                self.limits.max_blob_size
+            )));
         }
 
-        Ok(())
+        self.storage.append(&key, &data).await?;
+
+        db::Blob::touch(&self.db, &name.to_string()).await?;
+        self.describe_blob(caller, name).await
     }
 
-    async fn download(
+    async fn write_blob(
         &self,
-        hash: SHA256,
-        start: u64,
-        len: u64,
-    ) -> Result<Response<Vec<u8>>, Error> {
-        let path = self.file_path(&hash)?;
-        if !path.exists() {
-            return Ok(Response::Err("No such file".to_string()));
+        caller: NodeId,
+        name: BlobId,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Response<Blob>, Error> {
+        let key = match self.owned_blob(caller, &name).await? {
+            Response::Ok(key) => key,
+            Response::Err(e) => return Ok(Response::Err(e)),
+        };
+
+        let len = data.len() as u64;
+        if len > self.limits.max_append_size {
+            return Ok(Response::quota(format!(
+                "Write of {len} bytes exceeds limit of {}",
+                self.limits.max_append_size
+            )));
         }
 
-        let meta = tokio::fs::metadata(&path).await?;
-        if meta.size() < start + len {
-            return Ok(Response::Err("Data index out of bounds".to_string()));
+        if offset
+            .checked_add(len)
+            .is_none_or(|end| end > self.limits.max_blob_size)
+        {
+            return Ok(Response::quota(format!(
+                "Blob size would exceed limit of {}",
+                self.limits.max_blob_size
+            )));
         }
 
-        let mut file = tokio::fs::File::open(&path).await?;
-        file.seek(SeekFrom::Start(start)).await?;
+        let _blob_lock = self.key_locks.lock(&key).await;
 
-        let mut data = vec![0; len as usize];
-        file.read_exact(&mut data).await?;
+        match self.storage.write_at(&key, offset, &data).await {
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Response::not_found("No such blob"));
+            }
+            rsp => rsp?,
+        }
 
-        Ok(Response::Ok(data))
+        db::Blob::touch(&self.db, &name.to_string()).await?;
+        self.describe_blob(caller, name).await
     }
 
-    fn blob_path(&self, name: &str) -> Result<PathBuf, Error> {
-        let blobs_path = self.root.join(BLOB_DIR);
-        if !std::fs::exists(&blobs_path)? {
-            std::fs::create_dir(&blobs_path)?;
+    async fn owned_blob(&self, caller: NodeId, name: &BlobId) -> Result<Response<String>, Error> {
+        let key = blob_key(name);
+        if self.storage.stat(&key).await?.is_none() {
+            return Ok(Response::not_found("No such blob"));
         }
 
-        Ok(blobs_path.join(name))
+        match db::Blob::by_name(&self.db, &name.to_string()).await? {
+            None => Ok(Response::not_found("No such blob")),
+            Some(blob) if blob.owner != format!("{caller}") => {
+                Ok(Response::unauthorized("Blob belongs to another node"))
+            }
+            Some(_) => Ok(Response::Ok(key)),
+        }
     }
 
-    fn file_path(&self, hash: &SHA256) -> Result<PathBuf, Error> {
-        let files_path = self.root.join(FILE_DIR);
-        if !std::fs::exists(&files_path)? {
-            std::fs::create_dir(&files_path)?;
+    async fn list_blobs(&self, caller: NodeId) -> Result<Response<Vec<BlobInfo>>, Error> {
+        if !self.auth.admin(caller).await {
+            return Ok(Response::unauthorized("Admin access required"));
         }
 
-        Ok(files_path.join(hash))
-    }
-}
+        let mut blobs = vec![];
+        for blob in db::Blob::all(&self.db).await? {
+            let Ok(name) = BlobId::from_str(&blob.name) else {
+                continue;
+            };
 
-impl<A: NodeAuth + Send + Sync + 'static> ProtocolHandler for Server<A> {
-    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
-        let node_id = connection.remote_node_id()?;
-        tracing::info!(node_id = ?node_id, "accept");
-        if !self.auth.allow(node_id).await {
-            tracing::warn!(node_id = ?node_id, "unauthorized_client_node");
-            return Err(AcceptError::NotAllowed {});
+            let Some(meta) = self.storage.stat(&blob_key(&name)).await? else {
+                continue;
+            };
+
+            blobs.push(BlobInfo {
+                name,
+                owner: blob.owner,
+                size: meta.size,
+                created: blob.created.and_utc().timestamp(),
+                updated: blob.updated.and_utc().timestamp(),
+            });
+        }
+
+        Ok(Response::Ok(blobs))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn commit_blob(
+        &self,
+        caller: NodeId,
+        name: BlobId,
+        file_name: String,
+        tags: Vec<String>,
+        replace: bool,
+        expected_hash: Option<ContentHash>,
+        expected_size: Option<u64>,
+    ) -> Result<Response<File>, Error> {
+        let blob_key = match self.owned_blob(caller, &name).await? {
+            Response::Ok(key) => key,
+            Response::Err(e) => return Ok(Response::Err(e)),
+        };
+
+        // Held until the blob is committed, so no append lands after hashing
+        let _blob_lock = self.key_locks.lock(&blob_key).await;
+
+        let existing_file = match self.check_commit(&file_name, &tags, replace).await? {
+            Response::Ok(existing_file) => existing_file,
+            Response::Err(e) => return Ok(Response::Err(e)),
+        };
+
+        db::Blob::touch(&self.db, &name.to_string()).await?;
+
+        let Some(meta) = self.storage.stat(&blob_key).await? else {
+            return Ok(Response::not_found("No such blob"));
+        };
+
+        if let Some(expected_size) = expected_size
+            && expected_size != meta.size
+        {
+            return Ok(Response::conflict(format!(
+                "Blob size {} does not match expected size {expected_size}",
+                meta.size
+            )));
+        }
+
+        let hash = self.digest(&blob_key, meta.size).await?;
+        if let Some(expected_hash) = expected_hash
+            && expected_hash != hash
+        {
+            return Ok(Response::conflict(format!(
+                "Blob hash {hash} does not match expected hash {expected_hash}"
+            )));
+        }
+
+        self.commit_content(
+            caller,
+            &name,
+            meta.size,
+            hash,
+            file_name,
+            tags,
+            existing_file,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn upload(
+        &self,
+        caller: NodeId,
+        rx: &mut RecvStream,
+        file_name: String,
+        tags: Vec<String>,
+        replace: bool,
+        size: u64,
+        expected_hash: Option<ContentHash>,
+    ) -> Result<Response<File>, Error> {
+        if size > self.limits.max_blob_size {
+            rx.stop(0u32.into()).ok();
+            return Ok(Response::quota(format!(
+                "Upload of {size} bytes exceeds limit of {}",
+                self.limits.max_blob_size
+            )));
+        }
+
+        let existing_file = match self.check_commit(&file_name, &tags, replace).await? {
+            Response::Ok(existing_file) => existing_file,
+            Response::Err(e) => {
+                rx.stop(0u32.into()).ok();
+                return Ok(Response::Err(e));
+            }
+        };
+
+        let name = BlobId::new();
+        let _blob_lock = self.key_locks.lock(&blob_key(&name)).await;
+
+        db::Blob::insert(&self.db, &name.to_string(), &format!("{caller}")).await?;
+
+        let hash = match self.receive(rx, &blob_key(&name), size).await {
+            Ok(Response::Ok(hash)) => hash,
+            Ok(Response::Err(e)) => {
+                self.discard_blob(&name).await;
+                return Ok(Response::Err(e));
+            }
+            Err(e) => {
+                self.discard_blob(&name).await;
+                return Err(e);
+            }
+        };
+
+        if let Some(expected_hash) = expected_hash
+            && expected_hash != hash
+        {
+            self.discard_blob(&name).await;
+            return Ok(Response::conflict(format!(
+                "Upload hash {hash} does not match expected hash {expected_hash}"
+            )));
+        }
+
+        self.commit_content(caller, &name, size, hash, file_name, tags, existing_file)
+            .await
+    }
+
+    async fn discard_blob(&self, name: &BlobId) {
+        self.storage.delete(&blob_key(name)).await.ok();
+        db::Blob::delete(&self.db, &name.to_string()).await.ok();
+    }
+
+    async fn receive(
+        &self,
+        rx: &mut RecvStream,
+        key: &str,
+        size: u64,
+    ) -> Result<Response<ContentHash>, Error> {
+        let mut reader = sha256::HashingReader::new(&mut *rx);
+        match self.storage.put(key, &mut reader, size).await {
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Ok(Response::invalid_argument(
+                    "Upload ended before declared size",
+                ));
+            }
+            rsp => rsp?,
+        }
+
+        let hash = reader.finalize();
+        if rx.read_chunk(1, true).await?.is_some() {
+            rx.stop(0u32.into()).ok();
+            return Ok(Response::invalid_argument("Upload exceeds declared size"));
+        }
+
+        Ok(Response::Ok(hash))
+    }
+
+    async fn check_commit(
+        &self,
+        file_name: &str,
+        tags: &[String],
+        replace: bool,
+    ) -> Result<Response<Option<db::FileDesc>>, Error> {
+        if tags.is_empty() {
+            return Ok(Response::invalid_argument("At least one tag is required"));
+        }
+
+        for tag in tags.iter() {
+            if Tag::from_str(tag).is_err() {
+                return Ok(Response::invalid_argument(format!("Invalid tag {tag}")));
+            }
+        }
+
+        let existing_file = db::File::by_name(&self.db, file_name).await?;
+        if !replace && existing_file.is_some() {
+            return Ok(Response::conflict("File already exists"));
+        }
+
+        Ok(Response::Ok(existing_file))
+    }
+
+    async fn has_content(&self, hash: ContentHash) -> Result<Response<bool>, Error> {
+        let content = db::FileContent::by_hash(&self.db, hash.as_str()).await?;
+        Ok(Response::Ok(content.is_some()))
+    }
+
+    async fn commit_hash(
+        &self,
+        caller: NodeId,
+        hash: ContentHash,
+        file_name: String,
+        tags: Vec<String>,
+        replace: bool,
+    ) -> Result<Response<File>, Error> {
+        let existing_file = match self.check_commit(&file_name, &tags, replace).await? {
+            Response::Ok(existing_file) => existing_file,
+            Response::Err(e) => return Ok(Response::Err(e)),
+        };
+
+        let node = format!("{caller}");
+
+        let _lock = self.content_lock.lock().await;
+
+        let Some(content) = db::FileContent::by_hash(&self.db, hash.as_str()).await? else {
+            return Ok(Response::not_found("No such content"));
+        };
+
+        let size = content.size as u64;
+        let file = self
+            .commit_catalog(
+                None,
+                size,
+                &hash,
+                &Layout::Whole,
+                &file_name,
+                &tags,
+                &node,
+                existing_file,
+            )
+            .await?;
+
+        self.finish_deletes().await?;
+
+        let file = File {
+            name: file_name,
+            size,
+            hash,
+            created: file.created.and_utc().timestamp(),
+        };
+
+        Ok(Response::Ok(file))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn commit_content(
+        &self,
+        caller: NodeId,
+        blob: &BlobId,
+        size: u64,
+        hash: ContentHash,
+        file_name: String,
+        tags: Vec<String>,
+        existing_file: Option<db::FileDesc>,
+    ) -> Result<Response<File>, Error> {
+        let blob_key = blob_key(blob);
+        let node = format!("{caller}");
+
+        // Commits of the same content wait for each other, and nothing
+        // deletes its object while it is being stored
+        let content_key_lock = self.key_locks.lock(&file_key(&hash)).await;
+
+        let entry = db::Journal::insert(
+            &self.db,
+            db::Journal::COMMIT,
+            hash.as_str(),
+            Some(&blob.to_string()),
+        )
+        .await?;
+
+        // Existing content is reused under the lock, so it cannot be deleted
+        // before the new file refers to it. New content is stored without
+        // it, since no other commit can add a row for this hash meanwhile.
+        let mut lock = self.content_lock.lock().await;
+        let existing_content = db::FileContent::by_hash(&self.db, hash.as_str()).await?;
+
+        let mut chunk_pins = vec![];
+        let layout = match existing_content {
+            Some(_) => Layout::Whole,
+            None => {
+                drop(lock);
+
+                let layout = if self.chunking {
+                    self.store_chunks(&blob_key, size, &mut chunk_pins).await
+                } else {
+                    self.store_content(&blob_key, size, &hash, &tags).await
+                };
+                let layout = match layout {
+                    Ok(layout) => layout,
+                    Err(e) => {
+                        self.undo_commit(&entry).await?;
+                        drop(chunk_pins);
+                        self.finish_deletes().await?;
+                        return Err(e);
+                    }
+                };
+
+                lock = self.content_lock.lock().await;
+                layout
+            }
+        };
+
+        let file = match self
+            .commit_catalog(
+                Some(&entry),
+                size,
+                &hash,
+                &layout,
+                &file_name,
+                &tags,
+                &node,
+                existing_file,
+            )
+            .await
+        {
+            Ok(file) => file,
+            Err(e) => {
+                self.undo_commit(&entry).await?;
+                drop(lock);
+                drop(chunk_pins);
+                self.finish_deletes().await?;
+                return Err(e);
+            }
+        };
+
+        drop(lock);
+
+        if existing_content.is_some() || !matches!(layout, Layout::Whole) {
+            self.storage.delete(&blob_key).await.ok();
+        }
+
+        drop(chunk_pins);
+        drop(content_key_lock);
+        self.finish_deletes().await?;
+
+        let file = File {
+            name: file_name,
+            size,
+            hash,
+            created: file.created.and_utc().timestamp(),
+        };
+
+        Ok(Response::Ok(file))
+    }
+
+    async fn store_content(
+        &self,
+        key: &str,
+        size: u64,
+        hash: &ContentHash,
+        tags: &[String],
+    ) -> Result<Layout, Error> {
+        if let Some(policy) = self.compression.as_ref()
+            && policy.applies(size, tags)
+        {
+            // Compress into a staged copy next to the blob, which blob GC
+            // cleans up if the server stops before it is kept or dropped
+            let staged = format!("{key}.zst");
+            let _staged_lock = self.key_locks.lock(&staged).await;
+            let compressed = self.compress(policy, key, size, &staged).await;
+            if compressed.is_err() {
+                self.storage.delete(&staged).await.ok();
+            }
+
+            match compressed? {
+                Some(frames) => {
+                    self.storage.rename(&staged, &file_key(hash)).await?;
+                    return Ok(Layout::Compressed(frames));
+                }
+                None => self.storage.delete(&staged).await?,
+            }
+        }
+
+        self.storage.rename(key, &file_key(hash)).await?;
+        Ok(Layout::Whole)
+    }
+
+    async fn compress(
+        &self,
+        policy: &CompressionPolicy,
+        key: &str,
+        size: u64,
+        staged: &str,
+    ) -> Result<Option<Vec<(u64, u64)>>, Error> {
+        let reader = self.storage.get_range(key, 0, size).await?;
+        let mut compressor = policy.compressor(reader);
+
+        self.storage.put(staged, &mut tokio::io::empty(), 0).await?;
+        while let Some(frame) = compressor.next().await? {
+            self.storage.append(staged, &frame).await?;
+        }
+
+        Ok(compressor.finish())
+    }
+
+    async fn store_chunks(
+        &self,
+        key: &str,
+        size: u64,
+        pins: &mut Vec<PinGuard>,
+    ) -> Result<Layout, Error> {
+        let reader = self.storage.get_range(key, 0, size).await?;
+
+        let chunker = Chunker::default();
+        let mut split = chunker.split(reader);
+
+        let mut chunks = vec![];
+        while let Some((data, hash)) = split.next().await? {
+            let len = data.len() as u64;
+            let key = chunk_key(&hash);
+
+            // Pinned under its lock, so a delete either finishes first or
+            // leaves the chunk alone until the commit is done
+            let _chunk_lock = self.key_locks.lock(&key).await;
+            pins.push(self.key_pins.pin(&key));
+            if self.storage.stat(&key).await?.is_none() {
+                // Deleted again once unpinned, unless the commit links it
+                db::Journal::insert(&self.db, db::Journal::DELETE_CHUNK, hash.as_str(), None)
+                    .await?;
+                self.storage.put(&key, &mut data.as_slice(), len).await?;
+            }
+
+            chunks.push((hash, len));
+        }
+
+        Ok(Layout::Chunked(chunks))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn commit_catalog(
+        &self,
+        entry: Option<&db::Journal>,
+        size: u64,
+        hash: &ContentHash,
+        layout: &Layout,
+        file_name: &str,
+        tags: &[String],
+        node: &str,
+        existing_file: Option<db::FileDesc>,
+    ) -> Result<db::File, Error> {
+        let mut transaction = self.begin().await?;
+
+        if let Some(existing_file) = existing_file.as_ref() {
+            db::File::archive(&mut *transaction, existing_file.id).await?;
+        }
+
+        let content = match db::FileContent::by_hash(&mut *transaction, hash.as_str()).await? {
+            Some(content) => content,
+            None => {
+                let content = db::FileContent::insert(
+                    &mut *transaction,
+                    size as i64,
+                    hash.as_str(),
+                    node,
+                    matches!(layout, Layout::Chunked(_)),
+                    matches!(layout, Layout::Compressed(_)),
+                )
+                .await?;
+
+                match layout {
+                    Layout::Whole => {}
+                    Layout::Chunked(chunks) => {
+                        for (seq, (hash, size)) in chunks.iter().enumerate() {
+                            let chunk =
+                                match db::Chunk::by_hash(&mut *transaction, hash.as_str()).await? {
+                                    Some(chunk) => chunk,
+                                    None => {
+                                        db::Chunk::insert(
+                                            &mut *transaction,
+                                            hash.as_str(),
+                                            *size as i64,
+                                        )
+                                        .await?
+                                    }
+                                };
+
+                            db::Chunk::link(&mut *transaction, content.id, seq as i64, chunk.id)
+                                .await?;
+                        }
+                    }
+                    Layout::Compressed(frames) => {
+                        for (seq, (raw_size, size)) in frames.iter().enumerate() {
+                            db::Frame::insert(
+                                &mut *transaction,
+                                content.id,
+                                seq as i64,
+                                *raw_size as i64,
+                                *size as i64,
+                            )
+                            .await?;
+                        }
+                    }
+                }
+
+                content
+            }
+        };
+        let file = db::File::insert(&mut *transaction, file_name, content.id, node).await?;
+
+        for tag in tags.iter() {
+            let tag = match db::Tag::by_name(&mut *transaction, tag).await? {
+                Some(tag) => tag,
+                None => db::Tag::insert(&mut *transaction, tag).await?,
+            };
+
+            db::FileTag::insert(&mut *transaction, file.id, tag.id).await?;
+        }
+
+        if existing_file.is_some() {
+            self.prune_versions(&mut transaction, file_name).await?;
+        }
+
+        if let Some(entry) = entry {
+            if let Some(blob) = entry.blob.as_deref() {
+                db::Blob::delete(&mut *transaction, blob).await?;
+            }
+
+            db::Journal::delete(&mut *transaction, entry.id).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(file)
+    }
+
+    async fn undo_commit(&self, entry: &db::Journal) -> Result<(), Error> {
+        if db::FileContent::by_hash(&self.db, &entry.hash)
+            .await?
+            .is_none()
+        {
+            let file_key = file_key(&entry.hash);
+            let blob = entry.blob.as_deref().and_then(|b| BlobId::from_str(b).ok());
+
+            // A compressed copy sits next to the blob, a renamed one replaced it
+            if self.storage.stat(&file_key).await?.is_some() {
+                match blob.map(|b| blob_key(&b)) {
+                    Some(key) if self.storage.stat(&key).await?.is_none() => {
+                        self.storage.rename(&file_key, &key).await?
+                    }
+                    _ => self.storage.delete(&file_key).await?,
+                }
+            }
+        }
+
+        db::Journal::delete(&self.db, entry.id).await?;
+        Ok(())
+    }
+
+    async fn finish_deletes(&self) -> Result<(), Error> {
+        for op in [db::Journal::DELETE, db::Journal::DELETE_CHUNK] {
+            for entry in db::Journal::by_op(&self.db, op).await? {
+                self.finish_delete(&entry).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn finish_delete(&self, entry: &db::Journal) -> Result<(), Error> {
+        let key = match entry.op.as_str() {
+            db::Journal::DELETE_CHUNK => chunk_key(&entry.hash),
+            _ => file_key(&entry.hash),
+        };
+
+        // Objects in use by a commit are left for a later pass to settle
+        let Some(_lock) = self.key_locks.try_lock(&key) else {
+            return Ok(());
+        };
+        if self.key_pins.pinned(&key) {
+            return Ok(());
+        }
+
+        let referenced = match entry.op.as_str() {
+            db::Journal::DELETE_CHUNK => db::Chunk::by_hash(&self.db, &entry.hash).await?.is_some(),
+            _ => db::FileContent::by_hash(&self.db, &entry.hash)
+                .await?
+                .is_some_and(|c| !c.chunked),
+        };
+
+        if !referenced {
+            self.storage.delete(&key).await?;
+        }
+
+        db::Journal::delete(&self.db, entry.id).await?;
+        Ok(())
+    }
+
+    /// Settles journal entries left by an interrupted commit or delete, then
+    /// reconciles content objects against the catalog.
+    async fn recover(&self) -> Result<(), Error> {
+        let _lock = self.content_lock.lock().await;
+
+        for entry in db::Journal::all(&self.db).await? {
+            tracing::warn!(op = entry.op, hash = entry.hash, "recover_journal");
+            match entry.op.as_str() {
+                db::Journal::COMMIT => self.undo_commit(&entry).await?,
+                _ => self.finish_delete(&entry).await?,
+            }
+        }
+
+        self.reconcile().await
+    }
+
+    /// Moves content and chunk objects that no catalog row refers to into
+    /// quarantine, and logs rows whose objects are missing. Nothing is
+    /// deleted, since an unmounted root or misconfigured bucket looks the
+    /// same as lost data. Stray blobs are left to blob GC.
+    async fn reconcile(&self) -> Result<(), Error> {
+        for name in self.storage.list(FILE_DIR).await? {
+            let known = db::FileContent::by_hash(&self.db, &name)
+                .await?
+                .is_some_and(|c| !c.chunked);
+
+            if !known {
+                self.quarantine(&file_key(&name), &name).await?;
+            }
+        }
+
+        for name in self.storage.list(CHUNK_DIR).await? {
+            if db::Chunk::by_hash(&self.db, &name).await?.is_none() {
+                self.quarantine(&chunk_key(&name), &name).await?;
+            }
+        }
+
+        let mut after = String::new();
+        loop {
+            let contents = db::FileContent::page(&self.db, &after, 1_000).await?;
+            let Some(last) = contents.last() else {
+                break;
+            };
+            after = last.hash.clone();
+
+            for content in contents {
+                if !self.content_exists(&content).await? {
+                    tracing::warn!(hash = content.hash, "missing_content");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts a transaction holding the database write lock, so that one
+    /// which reads before writing waits for other writers instead of
+    /// failing with "database is locked".
+    async fn begin(&self) -> Result<sqlx::Transaction<'static, sqlx::Sqlite>, Error> {
+        Ok(self.db.begin_with("BEGIN IMMEDIATE").await?)
+    }
+
+    async fn content_exists(&self, content: &db::FileContent) -> Result<bool, Error> {
+        if !content.chunked {
+            let meta = self.storage.stat(&file_key(&content.hash)).await?;
+            return Ok(meta.is_some());
+        }
+
+        for chunk in db::Chunk::for_content(&self.db, content.id).await? {
+            if self.storage.stat(&chunk_key(&chunk.hash)).await?.is_none() {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub async fn gc_blobs(&self) -> Result<GcReport, Error> {
+        let ttl = chrono::Duration::from_std(self.blob_ttl).unwrap_or(chrono::Duration::MAX);
+        let cutoff = chrono::Utc::now()
+            .checked_sub_signed(ttl)
+            .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+
+        let blobs: HashMap<String, db::Blob> = db::Blob::all(&self.db)
+            .await?
+            .into_iter()
+            .map(|b| (b.name.clone(), b))
+            .collect();
+
+        let mut report = GcReport {
+            removed: vec![],
+            freed: 0,
+        };
+
+        for name in self.storage.list(BLOB_DIR).await? {
+            let key = blob_key(&name);
+
+            // Blobs being written or committed are in use, whatever their age
+            let Some(_blob_lock) = self.key_locks.try_lock(&key) else {
+                continue;
+            };
+
+            let Some(meta) = self.storage.stat(&key).await? else {
+                continue;
+            };
+
+            let modified = chrono::DateTime::<chrono::Utc>::from(meta.modified);
+            let updated = match blobs.get(&name) {
+                Some(blob) => blob.updated.and_utc().max(modified),
+                None => modified,
+            };
+
+            if updated > cutoff {
+                continue;
+            }
+
+            self.storage.delete(&key).await?;
+
+            db::Blob::delete(&self.db, &name).await?;
+            report.removed.push(name);
+            report.freed += meta.size;
+        }
+
+        for blob in blobs.values() {
+            let key = blob_key(&blob.name);
+            let Some(_blob_lock) = self.key_locks.try_lock(&key) else {
+                continue;
+            };
+
+            if blob.updated.and_utc() <= cutoff && self.storage.stat(&key).await?.is_none() {
+                db::Blob::delete(&self.db, &blob.name).await?;
+            }
+        }
+
+        tracing::info!(
+            removed = report.removed.len(),
+            freed = report.freed,
+            "gc_blobs"
+        );
+        Ok(report)
+    }
+
+    async fn list(
+        &self,
+        tag: String,
+        prefix: Option<String>,
+    ) -> Result<Response<Vec<File>>, Error> {
+        if Tag::from_str(&tag).is_err() {
+            return Ok(Response::invalid_argument(format!("Invalid tag {tag}")));
+        }
+
+        let term = prefix.as_deref().unwrap_or("");
+        let term = format!("{term}%");
+
+        let files = db::File::search(&self.db, &tag, &term)
+            .await?
+            .into_iter()
+            .map(From::from)
+            .collect();
+
+        let rsp = Response::Ok(files);
+        Ok(rsp)
+    }
+
+    async fn search(&self, tag: String, term: String) -> Result<Response<Vec<File>>, Error> {
+        if Tag::from_str(&tag).is_err() {
+            return Ok(Response::invalid_argument(format!("Invalid tag {tag}")));
+        }
+
+        let term = format!("%{term}%");
+
+        let files = db::File::search(&self.db, &tag, &term)
+            .await?
+            .into_iter()
+            .map(From::from)
+            .collect();
+
+        let rsp = Response::Ok(files);
+        Ok(rsp)
+    }
+
+    async fn describe(&self, name: String) -> Result<Response<FileDescription>, Error> {
+        match db::File::by_name(&self.db, &name).await? {
+            None => Ok(Response::not_found("No such file")),
+            Some(file) => {
+                let tags = db::FileTag::for_file(&self.db, file.id).await?;
+                let desc = FileDescription::new(file, tags);
+                Ok(Response::Ok(desc))
+            }
+        }
+    }
+
+    async fn delete(&self, caller: NodeId, name: String) -> Result<Response<String>, Error> {
+        let node = format!("{caller}");
+
+        let _lock = self.content_lock.lock().await;
+
+        if db::File::by_name(&self.db, &name).await?.is_none() {
+            return Ok(Response::not_found("No such file"));
+        }
+
+        let mut transaction = self.begin().await?;
+        self.trash_file(&mut transaction, &name, &node).await?;
+        transaction.commit().await?;
+
+        Ok(Response::ok())
+    }
+
+    async fn trash_file(
+        &self,
+        transaction: &mut sqlx::Transaction<'static, sqlx::Sqlite>,
+        name: &str,
+        node: &str,
+    ) -> Result<(), Error> {
+        // The version limit travels with the trashed versions, so a new file
+        // with this name starts with the default
+        let keep = db::VersionLimit::by_name(&mut **transaction, name)
+            .await?
+            .map(|limit| limit.keep);
+        let trash = db::Trash::insert(&mut **transaction, name, node, keep).await?;
+        db::File::trash(&mut **transaction, name, trash.id).await?;
+        db::VersionLimit::delete(&mut **transaction, name).await?;
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        caller: NodeId,
+        from: String,
+        to: String,
+        replace: bool,
+    ) -> Result<Response<File>, Error> {
+        if from == to {
+            return Ok(Response::invalid_argument(
+                "Source and destination are the same file",
+            ));
+        }
+
+        let node = format!("{caller}");
+
+        let _lock = self.content_lock.lock().await;
+
+        if db::File::by_name(&self.db, &from).await?.is_none() {
+            return Ok(Response::not_found("No such file"));
+        }
+
+        let existing_file = db::File::by_name(&self.db, &to).await?;
+        if !replace && existing_file.is_some() {
+            return Ok(Response::conflict("File already exists"));
+        }
+
+        let mut transaction = self.begin().await?;
+        if existing_file.is_some() {
+            self.trash_file(&mut transaction, &to, &node).await?;
+        }
+
+        db::File::rename(&mut *transaction, &from, &to).await?;
+        db::VersionLimit::rename(&mut *transaction, &from, &to).await?;
+
+        let Some(file) = db::File::by_name(&mut *transaction, &to).await? else {
+            return Ok(Response::not_found("No such file"));
+        };
+        transaction.commit().await?;
+
+        Ok(Response::Ok(file.into()))
+    }
+
+    async fn copy(
+        &self,
+        caller: NodeId,
+        from: String,
+        to: String,
+        tags: Option<Vec<String>>,
+    ) -> Result<Response<File>, Error> {
+        let node = format!("{caller}");
+
+        let _lock = self.content_lock.lock().await;
+
+        let Some(source) = db::File::by_name(&self.db, &from).await? else {
+            return Ok(Response::not_found("No such file"));
+        };
+
+        let tags = match tags {
+            Some(tags) => tags,
+            None => db::FileTag::for_file(&self.db, source.id).await?,
+        };
+
+        if let Response::Err(e) = self.check_commit(&to, &tags, false).await? {
+            return Ok(Response::Err(e));
+        }
+
+        let hash = ContentHash(source.hash);
+        let file = self
+            .commit_catalog(
+                None,
+                source.size as u64,
+                &hash,
+                &Layout::Whole,
+                &to,
+                &tags,
+                &node,
+                None,
+            )
+            .await?;
+
+        let file = File {
+            name: to,
+            size: source.size as u64,
+            hash,
+            created: file.created.and_utc().timestamp(),
+        };
+
+        Ok(Response::Ok(file))
+    }
+
+    async fn edit_tags(
+        &self,
+        selection: FileSelection,
+        add: Vec<String>,
+        remove: Vec<String>,
+    ) -> Result<Response<Vec<FileDescription>>, Error> {
+        if add.is_empty() && remove.is_empty() {
+            return Ok(Response::invalid_argument("No tags to add or remove"));
+        }
+
+        for tag in add.iter().chain(remove.iter()) {
+            if Tag::from_str(tag).is_err() {
+                return Ok(Response::invalid_argument(format!("Invalid tag {tag}")));
+            }
+
+            if add.contains(tag) && remove.contains(tag) {
+                return Ok(Response::invalid_argument(format!(
+                    "Tag {tag} is both added and removed"
+                )));
+            }
+        }
+
+        let mut transaction = self.begin().await?;
+
+        let files = match selection {
+            FileSelection::Names(names) => {
+                let mut files = vec![];
+                for name in names {
+                    match db::File::by_name(&mut *transaction, &name).await? {
+                        Some(file) => files.push(file),
+                        None => return Ok(Response::not_found(format!("No such file {name}"))),
+                    }
+                }
+
+                files
+            }
+            FileSelection::Search { tag, term } => {
+                if Tag::from_str(&tag).is_err() {
+                    return Ok(Response::invalid_argument(format!("Invalid tag {tag}")));
+                }
+
+                let term = format!("%{term}%");
+                db::File::search(&mut *transaction, &tag, &term).await?
+            }
+        };
+
+        let mut edited = vec![];
+        for file in files {
+            let current = db::FileTag::for_file(&mut *transaction, file.id).await?;
+
+            let mut tags: Vec<String> = current
+                .iter()
+                .filter(|t| !remove.contains(t))
+                .chain(add.iter().filter(|t| !current.contains(t)))
+                .cloned()
+                .collect();
+            tags.sort();
+            tags.dedup();
+
+            if tags.is_empty() {
+                return Ok(Response::invalid_argument(format!(
+                    "File {} must keep at least one tag",
+                    file.name
+                )));
+            }
+
+            for name in add.iter().filter(|t| !current.contains(t)) {
+                let tag = match db::Tag::by_name(&mut *transaction, name).await? {
+                    Some(tag) => tag,
+                    None => db::Tag::insert(&mut *transaction, name).await?,
+                };
+
+                db::FileTag::insert(&mut *transaction, file.id, tag.id).await?;
+            }
+
+            for name in remove.iter().filter(|t| current.contains(t)) {
+                if let Some(tag) = db::Tag::by_name(&mut *transaction, name).await? {
+                    db::FileTag::delete(&mut *transaction, file.id, tag.id).await?;
+                }
+            }
+
+            edited.push(FileDescription::new(file, tags));
+        }
+
+        db::Tag::prune(&mut *transaction).await?;
+        transaction.commit().await?;
+
+        Ok(Response::Ok(edited))
+    }
+
+    async fn trash(&self) -> Result<Response<Vec<TrashEntry>>, Error> {
+        let entries = db::Trash::list(&self.db)
+            .await?
+            .into_iter()
+            .map(From::from)
+            .collect();
+
+        Ok(Response::Ok(entries))
+    }
+
+    async fn restore_trash(&self, id: u64, name: Option<String>) -> Result<Response<File>, Error> {
+        let _lock = self.content_lock.lock().await;
+
+        let Some(trash) = db::Trash::by_id(&self.db, id as i64).await? else {
+            return Ok(Response::not_found("No such trash entry"));
+        };
+
+        let name = name.unwrap_or(trash.name);
+        if db::File::by_name(&self.db, &name).await?.is_some() {
+            return Ok(Response::conflict("File already exists"));
+        }
+
+        let mut transaction = self.begin().await?;
+        db::File::untrash(&mut *transaction, trash.id, &name).await?;
+        db::Trash::delete(&mut *transaction, trash.id).await?;
+        if let Some(keep) = trash.keep {
+            db::VersionLimit::set(&mut *transaction, &name, keep).await?;
+        }
+        self.prune_versions(&mut transaction, &name).await?;
+
+        let Some(file) = db::File::by_name(&mut *transaction, &name).await? else {
+            return Ok(Response::not_found("No such file"));
+        };
+
+        // Its tags may have been deleted while it was in the trash
+        if db::FileTag::for_file(&mut *transaction, file.id)
+            .await?
+            .is_empty()
+        {
+            return Ok(Response::conflict(
+                "Trashed file has no tags left to restore",
+            ));
+        }
+        transaction.commit().await?;
+
+        self.finish_deletes().await?;
+
+        Ok(Response::Ok(file.into()))
+    }
+
+    async fn purge_trash(&self, id: Option<u64>) -> Result<Response<u64>, Error> {
+        let _lock = self.content_lock.lock().await;
+
+        let entries = match id {
+            Some(id) => match db::Trash::by_id(&self.db, id as i64).await? {
+                Some(trash) => vec![trash],
+                None => return Ok(Response::not_found("No such trash entry")),
+            },
+            None => db::Trash::all(&self.db).await?,
+        };
+
+        let purged = self.purge(&entries).await?;
+        Ok(Response::Ok(purged))
+    }
+
+    /// Purges trash entries older than the trash retention, returning how
+    /// many were removed.
+    pub async fn expire_trash(&self) -> Result<u64, Error> {
+        let retention =
+            chrono::Duration::from_std(self.trash_retention).unwrap_or(chrono::Duration::MAX);
+        let cutoff = chrono::Utc::now()
+            .checked_sub_signed(retention)
+            .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+
+        let _lock = self.content_lock.lock().await;
+
+        let entries: Vec<_> = db::Trash::all(&self.db)
+            .await?
+            .into_iter()
+            .filter(|t| t.deleted.and_utc() <= cutoff)
+            .collect();
+
+        let purged = self.purge(&entries).await?;
+        tracing::info!(purged, "expire_trash");
+        Ok(purged)
+    }
+
+    async fn purge(&self, entries: &[db::Trash]) -> Result<u64, Error> {
+        let mut transaction = self.begin().await?;
+        for trash in entries {
+            for file in db::File::in_trash(&mut *transaction, trash.id).await? {
+                db::File::delete(&mut *transaction, file.id).await?;
+                self.gc_content(&mut transaction, file.content_id).await?;
+            }
+
+            db::Trash::delete(&mut *transaction, trash.id).await?;
         }
+        db::Tag::prune(&mut *transaction).await?;
+        transaction.commit().await?;
 
-        let (mut tx, mut rx) = connection.accept_bi().await?;
+        self.finish_deletes().await?;
 
-        let mut data = vec![];
-        while let Some(chunk) = rx
-            .read_chunk(100_000, true)
-            .await
-            .map_err(AcceptError::from_err)?
+        Ok(entries.len() as u64)
+    }
+
+    async fn versions(&self, name: String) -> Result<Response<Vec<FileVersion>>, Error> {
+        let files = db::File::versions(&self.db, &name).await?;
+        if files.is_empty() {
+            return Ok(Response::not_found("No such file"));
+        }
+
+        let mut versions = vec![];
+        for file in files {
+            let tags = db::FileTag::for_file(&self.db, file.id).await?;
+            versions.push(FileVersion::new(file, tags));
+        }
+
+        Ok(Response::Ok(versions))
+    }
+
+    async fn restore_version(
+        &self,
+        caller: NodeId,
+        name: String,
+        version: u64,
+    ) -> Result<Response<File>, Error> {
+        let node = format!("{caller}");
+
+        let _lock = self.content_lock.lock().await;
+
+        let Some(current) = db::File::by_name(&self.db, &name).await? else {
+            return Ok(Response::not_found("No such file"));
+        };
+
+        let versions = db::File::versions(&self.db, &name).await?;
+        let Some(old) = versions.into_iter().find(|f| f.version as u64 == version) else {
+            return Ok(Response::not_found("No such version"));
+        };
+
+        if !old.archived {
+            return Ok(Response::conflict(format!(
+                "Version {version} is already current"
+            )));
+        }
+
+        // Its tags may have been deleted while it was archived
+        let tags = db::FileTag::for_file(&self.db, old.id).await?;
+        if tags.is_empty() {
+            return Ok(Response::conflict(format!(
+                "Version {version} has no tags left to restore"
+            )));
+        }
+
+        let hash = ContentHash(old.hash);
+        let file = self
+            .commit_catalog(
+                None,
+                old.size as u64,
+                &hash,
+                &Layout::Whole,
+                &name,
+                &tags,
+                &node,
+                Some(current),
+            )
+            .await?;
+
+        self.finish_deletes().await?;
+
+        let file = File {
+            name,
+            size: old.size as u64,
+            hash,
+            created: file.created.and_utc().timestamp(),
+        };
+
+        Ok(Response::Ok(file))
+    }
+
+    async fn set_version_limit(
+        &self,
+        name: String,
+        keep: Option<u32>,
+    ) -> Result<Response<String>, Error> {
+        if keep == Some(0) {
+            return Ok(Response::invalid_argument(
+                "At least one version must be kept",
+            ));
+        }
+
+        let _lock = self.content_lock.lock().await;
+
+        if db::File::by_name(&self.db, &name).await?.is_none() {
+            return Ok(Response::not_found("No such file"));
+        }
+
+        let mut transaction = self.begin().await?;
+        match keep {
+            Some(keep) => db::VersionLimit::set(&mut *transaction, &name, keep as i64).await?,
+            None => db::VersionLimit::delete(&mut *transaction, &name).await?,
+        };
+        self.prune_versions(&mut transaction, &name).await?;
+        transaction.commit().await?;
+
+        self.finish_deletes().await?;
+
+        Ok(Response::ok())
+    }
+
+    async fn prune_versions(
+        &self,
+        transaction: &mut sqlx::Transaction<'static, sqlx::Sqlite>,
+        name: &str,
+    ) -> Result<(), Error> {
+        let keep = match db::VersionLimit::by_name(&mut **transaction, name).await? {
+            Some(limit) => limit.keep,
+            None => self.max_versions as i64,
+        };
+
+        for file in db::File::expired(&mut **transaction, name, keep).await? {
+            db::File::delete(&mut **transaction, file.id).await?;
+            self.gc_content(transaction, file.content_id).await?;
+        }
+
+        db::Tag::prune(&mut **transaction).await?;
+        Ok(())
+    }
+
+    async fn gc_content(
+        &self,
+        transaction: &mut sqlx::Transaction<'static, sqlx::Sqlite>,
+        content_id: i64,
+    ) -> Result<(), Error> {
+        if let Some(content) =
+            db::FileContent::find_orphaned(&mut **transaction, content_id).await?
         {
-            let mut bytes = chunk.bytes.to_vec();
-            data.append(&mut bytes);
+            self.remove_content(transaction, &content).await?;
         }
 
-        let cmd: Cmd = bincode::decode_from_slice(&data, self.bincode_config)
-            .map_err(AcceptError::from_err)?
-            .0;
+        Ok(())
+    }
 
-        let rsp = self.handle(node_id, cmd.clone()).await;
-        if rsp.is_err() {
-            tracing::warn!(cmd = ?cmd, rsp = ?rsp, "handle_failed");
+    async fn remove_content(
+        &self,
+        transaction: &mut sqlx::Transaction<'static, sqlx::Sqlite>,
+        content: &db::FileContent,
+    ) -> Result<(), Error> {
+        if content.chunked {
+            for chunk in db::Chunk::orphaned_by(&mut **transaction, content.id).await? {
+                db::Journal::insert(
+                    &mut **transaction,
+                    db::Journal::DELETE_CHUNK,
+                    &chunk.hash,
+                    None,
+                )
+                .await?;
+                db::Chunk::delete(&mut **transaction, chunk.id).await?;
+            }
+        } else {
+            db::Journal::insert(&mut **transaction, db::Journal::DELETE, &content.hash, None)
+                .await?;
+        }
+
+        db::FileContent::delete(&mut **transaction, content.id).await?;
+        Ok(())
+    }
+
+    async fn download(
+        &self,
+        hash: ContentHash,
+        start: u64,
+        len: u64,
+    ) -> Result<Response<Vec<u8>>, Error> {
+        if len > self.limits.max_download_len {
+            return Ok(Response::quota(format!(
+                "Download of {len} bytes exceeds limit of {}",
+                self.limits.max_download_len
+            )));
+        }
+
+        let mut file = match self.open_range(&hash, start, len).await? {
+            Response::Ok(file) => file,
+            Response::Err(e) => return Ok(Response::Err(e)),
+        };
+
+        let mut data = vec![0; len as usize];
+        file.read_exact(&mut data).await?;
+
+        Ok(Response::Ok(data))
+    }
+
+    async fn stream(
+        &self,
+        tx: &mut SendStream,
+        hash: ContentHash,
+        start: u64,
+        len: u64,
+    ) -> Result<(), Error> {
+        let mut file = match self.open_range(&hash, start, len).await {
+            Ok(Response::Ok(file)) => file,
+            Ok(Response::Err(e)) => {
+                let rsp: Response<u64> = Response::Err(e);
+                return frame::write(tx, &rsp, self.bincode_config).await;
+            }
+            Err(e) => {
+                tracing::warn!(err = ?e, "stream_failed");
+                let rsp: Response<u64> = Response::Err(internal());
+                return frame::write(tx, &rsp, self.bincode_config).await;
+            }
+        };
+
+        frame::write(tx, &Response::Ok(len), self.bincode_config).await?;
+
+        let copied = tokio::io::copy(&mut file, tx).await;
+        if !matches!(copied, Ok(n) if n == len) {
+            tx.reset(1u32.into()).ok();
+            copied?;
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<Response<Stats>, Error> {
+        let stats = db::Stats::get(&self.db).await?;
+        Ok(Response::Ok(stats.into()))
+    }
+
+    async fn rotate_keys(&self, caller: NodeId) -> Result<Response<RotationReport>, Error> {
+        if !self.auth.admin(caller).await {
+            return Ok(Response::unauthorized("Admin access required"));
+        }
+
+        match self.storage.rotate_keys().await? {
+            Some(rewrapped) => Ok(Response::Ok(RotationReport { rewrapped })),
+            None => Ok(Response::invalid_argument("Storage is not encrypted")),
+        }
+    }
+
+    async fn scrub(
+        &self,
+        after: Option<ContentHash>,
+        limit: u32,
+        quarantine: bool,
+    ) -> Result<Response<ScrubReport>, Error> {
+        if limit == 0 || limit > MAX_SCRUB_BATCH {
+            return Ok(Response::invalid_argument(format!(
+                "Scrub limit must be between 1 and {MAX_SCRUB_BATCH}"
+            )));
+        }
+
+        let start = after.as_ref().map(|h| h.as_str()).unwrap_or("");
+        let contents = db::FileContent::page(&self.db, start, limit as i64).await?;
+        let next = match contents.last() {
+            Some(content) if contents.len() == limit as usize => {
+                Some(ContentHash(content.hash.clone()))
+            }
+            _ => None,
+        };
+
+        let mut report = ScrubReport {
+            checked: contents.len() as u64,
+            corrupted: vec![],
+            missing: vec![],
+            orphaned: vec![],
+            corrupted_chunks: vec![],
+            orphaned_chunks: vec![],
+            next: next.clone(),
+        };
+
+        for content in contents {
+            let hash = ContentHash(content.hash.clone());
+
+            match self.digest_content(&content).await {
+                Ok(digest) if digest == hash => {}
+                Ok(_) => report.corrupted.push(hash),
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                    report.missing.push(hash)
+                }
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    report.corrupted.push(hash)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut missing = vec![];
+        for hash in report.missing {
+            if let Some(content) = db::FileContent::by_hash(&self.db, hash.as_str()).await?
+                && !self.content_exists(&content).await?
+            {
+                missing.push(hash);
+            }
+        }
+        report.missing = missing;
+
+        // Objects are named by hash, and each batch covers the hash range of
+        // its content. Names that are not hashes go with the first batch.
+        let in_batch = |name: &str| match ContentHash::from_str(name) {
+            Ok(hash) => {
+                after.as_ref().is_none_or(|after| &hash > after)
+                    && next.as_ref().is_none_or(|next| &hash <= next)
+            }
+            Err(_) => after.is_none(),
+        };
+
+        // Each object is locked on its own, and one that a commit or delete
+        // holds is left for a later scrub
+        if quarantine {
+            for hash in report.corrupted.iter() {
+                let key = file_key(hash);
+                let Some(_lock) = self.key_locks.try_lock(&key) else {
+                    continue;
+                };
+
+                if let Some(content) = db::FileContent::by_hash(&self.db, hash.as_str()).await?
+                    && !content.chunked
+                    && self.digest_content(&content).await.ok().as_ref() != Some(hash)
+                {
+                    self.quarantine(&key, hash.as_str()).await?;
+                }
+            }
+        }
+
+        for name in self.storage.list(FILE_DIR).await? {
+            if !in_batch(&name) {
+                continue;
+            }
+
+            let key = file_key(&name);
+            let Some(_lock) = self.key_locks.try_lock(&key) else {
+                continue;
+            };
+
+            let known = db::FileContent::by_hash(&self.db, &name)
+                .await?
+                .is_some_and(|c| !c.chunked);
+
+            if !known {
+                if quarantine {
+                    self.quarantine(&key, &name).await?;
+                }
+                report.orphaned.push(name);
+            }
+        }
+        report.orphaned.sort();
+
+        for name in self.storage.list(CHUNK_DIR).await? {
+            if !in_batch(&name) {
+                continue;
+            }
+
+            let key = chunk_key(&name);
+            let Some(_lock) = self.key_locks.try_lock(&key) else {
+                continue;
+            };
+            if self.key_pins.pinned(&key) {
+                continue;
+            }
+
+            let Some(chunk) = db::Chunk::by_hash(&self.db, &name).await? else {
+                if quarantine {
+                    self.quarantine(&key, &name).await?;
+                }
+                report.orphaned_chunks.push(name);
+                continue;
+            };
+
+            let hash = ContentHash(chunk.hash);
+            let intact = match self.digest(&key, chunk.size as u64).await {
+                Ok(digest) => digest == hash,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => true,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::InvalidData => false,
+                Err(e) => return Err(e),
+            };
+
+            if !intact {
+                if quarantine {
+                    self.quarantine(&key, &name).await?;
+                }
+                report.corrupted_chunks.push(hash);
+            }
+        }
+        report.orphaned_chunks.sort();
+        report.corrupted_chunks.sort();
+
+        Ok(Response::Ok(report))
+    }
+
+    async fn quarantine(&self, key: &str, name: &str) -> Result<(), Error> {
+        if self.storage.stat(key).await?.is_some() {
+            tracing::warn!(key, "quarantine_content");
+            self.storage
+                .rename(key, &format!("{QUARANTINE_DIR}/{name}"))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn digest_content(&self, content: &db::FileContent) -> Result<ContentHash, Error> {
+        if content.chunked {
+            let reader = self.chunk_reader(content, 0, content.size as u64).await?;
+            return sha256::digest(reader).await;
+        }
+
+        if content.compressed {
+            let reader = self.frame_reader(content, 0, content.size as u64).await?;
+            return sha256::digest(reader).await;
+        }
+
+        let key = file_key(&content.hash);
+        let Some(meta) = self.storage.stat(&key).await? else {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+        };
+
+        self.digest(&key, meta.size).await
+    }
+
+    async fn digest(&self, key: &str, size: u64) -> Result<ContentHash, Error> {
+        let reader = self.storage.get_range(key, 0, size).await?;
+        sha256::digest(reader).await
+    }
+
+    async fn open_range(
+        &self,
+        hash: &ContentHash,
+        start: u64,
+        len: u64,
+    ) -> Result<Response<ObjectReader>, Error> {
+        let size = match db::FileContent::by_hash(&self.db, hash.as_str()).await? {
+            Some(content) if content.chunked || content.compressed => content.size as u64,
+            _ => match self.storage.stat(&file_key(hash)).await? {
+                Some(meta) => meta.size,
+                None => return Ok(Response::not_found("No such file")),
+            },
+        };
+
+        if start.checked_add(len).is_none_or(|end| end > size) {
+            return Ok(Response::out_of_range("Data index out of bounds"));
+        }
+
+        if let Some(content) = db::FileContent::by_hash(&self.db, hash.as_str()).await? {
+            if content.chunked {
+                let reader = self.chunk_reader(&content, start, len).await?;
+                return Ok(Response::Ok(Box::new(reader)));
+            }
+
+            if content.compressed {
+                let reader = self.frame_reader(&content, start, len).await?;
+                return Ok(Response::Ok(reader));
+            }
+        }
+
+        let reader = self.storage.get_range(&file_key(hash), start, len).await?;
+        Ok(Response::Ok(reader))
+    }
+
+    async fn chunk_reader(
+        &self,
+        content: &db::FileContent,
+        start: u64,
+        len: u64,
+    ) -> Result<ChunkReader<S>, Error> {
+        let chunks = db::Chunk::for_content(&self.db, content.id)
+            .await?
+            .into_iter()
+            .map(|c| (chunk_key(&c.hash), c.size as u64))
+            .collect();
+
+        Ok(ChunkReader::new(self.storage.clone(), chunks, start, len))
+    }
+
+    async fn frame_reader(
+        &self,
+        content: &db::FileContent,
+        start: u64,
+        len: u64,
+    ) -> Result<ObjectReader, Error> {
+        let end = start + len;
+        let mut first = None;
+        let mut frames = vec![];
+        let (mut raw_offset, mut offset) = (0, 0);
+        for frame in db::Frame::for_content(&self.db, content.id).await? {
+            let (raw_size, size) = (frame.raw_size as u64, frame.size as u64);
+            if raw_offset < end && raw_offset + raw_size > start {
+                first.get_or_insert_with(|| (offset, start - raw_offset));
+                frames.push((raw_size, size));
+            }
+
+            raw_offset += raw_size;
+            offset += size;
+        }
+
+        let Some((offset, skip)) = first else {
+            return Ok(Box::new(tokio::io::empty()));
+        };
+
+        let stored = frames.iter().map(|(_, size)| size).sum();
+        let reader = self
+            .storage
+            .get_range(&file_key(&content.hash), offset, stored)
+            .await?;
+        let reader = compression::decompress(reader, frames, skip as usize);
+        Ok(Box::new(reader.take(len)))
+    }
+}
+
+impl<A: NodeAuth + Send + Sync + 'static, S: Storage> ProtocolHandler for Server<A, S> {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        let node_id = connection.remote_node_id()?;
+        tracing::info!(node_id = ?node_id, "accept");
+        if !self.auth.allow(node_id).await {
+            tracing::warn!(node_id = ?node_id, "unauthorized_client_node");
+            return Err(AcceptError::NotAllowed {});
         }
 
-        let rsp = rsp.map_err(AcceptError::from_err)?;
+        let (mut tx, mut rx) = connection.accept_bi().await?;
+        let hello: Hello = frame::read(&mut rx, self.bincode_config, MAX_HELLO_SIZE)
+            .await
+            .map_err(AcceptError::from_err)?;
 
-        tx.write_all(&rsp).await.map_err(AcceptError::from_err)?;
+        frame::write(&mut tx, &ServerInfo::current(), self.bincode_config)
+            .await
+            .map_err(AcceptError::from_err)?;
         tx.finish()?;
-        connection.closed().await;
+
+        if hello.version != PROTOCOL_VERSION {
+            tracing::warn!(node_id = ?node_id, version = hello.version, "incompatible_client");
+            connection.closed().await;
+            return Ok(());
+        }
+
+        loop {
+            let (tx, rx) = match connection.accept_bi().await {
+                Ok(streams) => streams,
+                Err(e) => {
+                    tracing::info!(node_id = ?node_id, err = ?e, "connection_closed");
+                    break;
+                }
+            };
+
+            let server = self.clone();
+            tokio::spawn(async move { server.serve(node_id, tx, rx).await });
+        }
 
         Ok(())
     }
 }
 
+fn blob_key(name: impl Display) -> String {
+    format!("{BLOB_DIR}/{name}")
+}
+
+fn file_key(hash: impl Display) -> String {
+    format!("{FILE_DIR}/{hash}")
+}
+
+fn chunk_key(hash: impl Display) -> String {
+    format!("{CHUNK_DIR}/{hash}")
+}
+
+fn internal() -> Failure {
+    Failure::Internal("Internal server error".to_string())
+}
+
 async fn setup_db(db: &str) -> Result<SqlitePool, sqlx::Error> {
     let opts = SqliteConnectOptions::new()
         .filename(db)
@@ -1,17 +1,67 @@
-use std::path::PathBuf;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
 
 use sha2::{Digest, Sha256};
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
 
-use crate::{Error, SHA256};
+use crate::{ContentHash, Error};
 
-pub async fn digest(path: &PathBuf) -> Result<SHA256, Error> {
-    let mut hasher = Sha256::new();
-    let mut file = tokio::fs::File::open(path).await?;
+#[derive(Default)]
+pub struct Hasher(Sha256);
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finalize(self) -> ContentHash {
+        let hash = self.0.finalize();
+        ContentHash(data_encoding::HEXLOWER.encode(&hash))
+    }
+}
+
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Hasher::default(),
+        }
+    }
+
+    pub fn finalize(self) -> ContentHash {
+        self.hasher.finalize()
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let start = buf.filled().len();
+        let rsp = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = rsp {
+            self.hasher.update(&buf.filled()[start..]);
+        }
+
+        rsp
+    }
+}
+
+pub async fn digest<R: AsyncRead + Unpin>(mut reader: R) -> Result<ContentHash, Error> {
+    let mut hasher = Hasher::default();
     let mut buf = [0u8; 10_000];
 
     loop {
-        let n = file.read(&mut buf).await?;
+        let n = reader.read(&mut buf).await?;
         if n == 0 {
             break;
         }
@@ -19,9 +69,7 @@ pub async fn digest(path: &PathBuf) -> Result<SHA256, Error> {
         hasher.update(&buf[0..n]);
     }
 
-    let hash = hasher.finalize();
-    let hash = data_encoding::HEXLOWER.encode(&hash);
-    Ok(hash)
+    Ok(hasher.finalize())
 }
 
 #[cfg(test)]
@@ -40,9 +88,15 @@ mod tests {
         f2.write_all(b"test2").unwrap();
         f3.write_all(b"test1").unwrap();
 
-        let h1 = super::digest(&f1.path().to_path_buf()).await.unwrap();
-        let h2 = super::digest(&f2.path().to_path_buf()).await.unwrap();
-        let h3 = super::digest(&f3.path().to_path_buf()).await.unwrap();
+        let h1 = super::digest(tokio::fs::File::open(f1.path()).await.unwrap())
+            .await
+            .unwrap();
+        let h2 = super::digest(tokio::fs::File::open(f2.path()).await.unwrap())
+            .await
+            .unwrap();
+        let h3 = super::digest(tokio::fs::File::open(f3.path()).await.unwrap())
+            .await
+            .unwrap();
 
         assert!(h1 != h2);
         assert!(h1 == h3);
@@ -0,0 +1,66 @@
+mod encrypted;
+mod local;
+mod s3;
+
+use std::time::SystemTime;
+
+use tokio::io::AsyncRead;
+
+use crate::Error;
+
+pub use encrypted::{EncryptedStorage, MasterKey};
+pub use local::LocalStorage;
+pub use s3::{S3Config, S3Storage};
+
+pub type ObjectReader = Box<dyn AsyncRead + Send + Unpin>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectMeta {
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Byte store behind the catalog. Keys are `<dir>/<name>`, and a missing
+/// object is reported as an `std::io::ErrorKind::NotFound` I/O error.
+pub trait Storage: Send + Sync + 'static {
+    /// Stores exactly `size` bytes from `reader`, replacing the object
+    /// atomically. Fails with `UnexpectedEof` if the reader ends early.
+    fn put(
+        &self,
+        key: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+        size: u64,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn append(&self, key: &str, data: &[u8]) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn write_at(
+        &self,
+        key: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn get_range(
+        &self,
+        key: &str,
+        start: u64,
+        len: u64,
+    ) -> impl Future<Output = Result<ObjectReader, Error>> + Send;
+
+    fn rename(&self, from: &str, to: &str) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Removes the object, succeeding if it is already gone.
+    fn delete(&self, key: &str) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Names of the objects directly under `dir`.
+    fn list(&self, dir: &str) -> impl Future<Output = Result<Vec<String>, Error>> + Send;
+
+    fn stat(&self, key: &str) -> impl Future<Output = Result<Option<ObjectMeta>, Error>> + Send;
+
+    /// Rewraps data keys under the current master key, returning how many
+    /// changed, or `None` if the backend does not encrypt.
+    fn rotate_keys(&self) -> impl Future<Output = Result<Option<u64>, Error>> + Send {
+        async { Ok(None) }
+    }
+}
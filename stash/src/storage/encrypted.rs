@@ -0,0 +1,579 @@
+use std::{fmt::Debug, io, str::FromStr, sync::Arc};
+
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use data_encoding::HEXLOWER_PERMISSIVE;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::{ObjectMeta, ObjectReader, Storage};
+use crate::{Error, blocks::Blocks};
+
+const KEY_DIR: &str = "keys";
+const FORMAT_KEY: &str = "meta/encryption";
+const FORMAT: &str = "xchacha20poly1305-64k";
+/// Directories that hold content, which must be empty before the store is
+/// first encrypted.
+const DATA_DIRS: [&str; 4] = ["files", "blobs", "chunks", "quarantine"];
+const MAGIC: &[u8; 8] = b"STASHEC1";
+const KEY_SIZE: usize = 32;
+const KEY_ID_SIZE: usize = 16;
+const MASTER_ID_SIZE: usize = 8;
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+const HEADER_SIZE: u64 = (MAGIC.len() + KEY_ID_SIZE) as u64;
+const SEGMENT_SIZE: u64 = 64 * 1024;
+const SEGMENT_OVERHEAD: u64 = (NONCE_SIZE + TAG_SIZE) as u64;
+
+type KeyId = [u8; KEY_ID_SIZE];
+
+#[derive(Clone)]
+pub struct MasterKey {
+    key: [u8; KEY_SIZE],
+    id: [u8; MASTER_ID_SIZE],
+}
+
+impl MasterKey {
+    pub fn new(key: [u8; KEY_SIZE]) -> Self {
+        let digest = Sha256::digest(key);
+        let mut id = [0; MASTER_ID_SIZE];
+        id.copy_from_slice(&digest[..MASTER_ID_SIZE]);
+
+        Self { key, id }
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.key.into())
+    }
+}
+
+impl FromStr for MasterKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = HEXLOWER_PERMISSIVE
+            .decode(s.trim().as_bytes())
+            .ok()
+            .and_then(|k| <[u8; KEY_SIZE]>::try_from(k).ok())
+            .ok_or_else(|| {
+                Error::InvalidArgument("Master key must be 64 hex characters".to_string())
+            })?;
+
+        Ok(Self::new(key))
+    }
+}
+
+impl Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MasterKey({})", HEXLOWER_PERMISSIVE.encode(&self.id))
+    }
+}
+
+/// Encrypts objects of an inner backend with XChaCha20-Poly1305.
+///
+/// Each object gets its own data key, stored under `keys/` wrapped by the
+/// master key, and a header naming that key. Content is sealed in 64 KiB
+/// segments so ranged reads only decrypt the segments they touch. The format
+/// is recorded in `meta/encryption`.
+#[derive(Clone, Debug)]
+pub struct EncryptedStorage<S> {
+    inner: S,
+    master: MasterKey,
+    previous: Vec<MasterKey>,
+    key_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<S: Storage> EncryptedStorage<S> {
+    /// Opens `inner`, marking it as encrypted if it is empty and refusing
+    /// it if it already holds unencrypted content. `previous` master keys
+    /// are only used to unwrap data keys that have not been rotated yet.
+    pub async fn open(
+        inner: S,
+        master: MasterKey,
+        previous: Vec<MasterKey>,
+    ) -> Result<Self, Error> {
+        let storage = Self {
+            inner,
+            master,
+            previous,
+            key_lock: Arc::default(),
+        };
+
+        if storage.inner.stat(FORMAT_KEY).await?.is_some() {
+            let format = storage.read_all(FORMAT_KEY).await?;
+            let format = String::from_utf8_lossy(&format);
+            if format.trim() != FORMAT {
+                return Err(Error::StorageError(format!(
+                    "Unsupported encryption format {:?} in {FORMAT_KEY}",
+                    format.trim()
+                )));
+            }
+
+            return Ok(storage);
+        }
+
+        for dir in DATA_DIRS {
+            if !storage.inner.list(dir).await?.is_empty() {
+                return Err(Error::StorageError(format!(
+                    "Storage holds unencrypted objects in {dir}, so it cannot be encrypted"
+                )));
+            }
+        }
+
+        let format = format!("{FORMAT}\n");
+        storage
+            .inner
+            .put(FORMAT_KEY, &mut format.as_bytes(), format.len() as u64)
+            .await?;
+
+        Ok(storage)
+    }
+
+    /// Whether `inner` has been opened as encrypted storage before, and so
+    /// must not be served without its master key.
+    pub async fn is_encrypted(inner: &S) -> Result<bool, Error> {
+        Ok(inner.stat(FORMAT_KEY).await?.is_some())
+    }
+
+    async fn delete_key(&self, id: &KeyId) -> Result<(), Error> {
+        let _lock = self.key_lock.lock().await;
+        self.inner.delete(&data_key(id)).await
+    }
+
+    async fn key_id(&self, key: &str) -> Result<Option<KeyId>, Error> {
+        let mut header = [0; HEADER_SIZE as usize];
+        let read = match self.inner.get_range(key, 0, HEADER_SIZE).await {
+            Err(Error::IoError(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            reader => reader?.read_exact(&mut header).await,
+        };
+
+        if read.is_err() || &header[..MAGIC.len()] != MAGIC {
+            return Err(Error::StorageError(format!("{key} is not encrypted")));
+        }
+
+        let mut id = [0; KEY_ID_SIZE];
+        id.copy_from_slice(&header[MAGIC.len()..]);
+        Ok(Some(id))
+    }
+
+    async fn cipher(&self, key: &str) -> Result<XChaCha20Poly1305, Error> {
+        let Some(id) = self.key_id(key).await? else {
+            return Err(not_found(key));
+        };
+
+        let wrapped = self.read_all(&data_key(&id)).await?;
+        let key = self.unwrap_key(&id, &wrapped)?;
+        Ok(XChaCha20Poly1305::new(&key.into()))
+    }
+
+    async fn read_all(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let Some(meta) = self.inner.stat(key).await? else {
+            return Err(not_found(key));
+        };
+
+        let mut data = vec![];
+        self.inner
+            .get_range(key, 0, meta.size)
+            .await?
+            .read_to_end(&mut data)
+            .await?;
+
+        Ok(data)
+    }
+
+    async fn plain_size(&self, key: &str) -> Result<Option<u64>, Error> {
+        let Some(meta) = self.inner.stat(key).await? else {
+            return Ok(None);
+        };
+
+        let size = plain_size(meta.size)
+            .ok_or_else(|| Error::StorageError(format!("{key} has invalid encrypted size")))?;
+
+        Ok(Some(size))
+    }
+
+    async fn open_range(
+        &self,
+        key: &str,
+        cipher: XChaCha20Poly1305,
+        size: u64,
+        start: u64,
+        len: u64,
+    ) -> Result<ObjectReader, Error> {
+        let len = len.min(size.saturating_sub(start));
+        if len == 0 {
+            return Ok(Box::new(tokio::io::empty()));
+        }
+
+        let first = start / SEGMENT_SIZE;
+        let last = (start + len - 1) / SEGMENT_SIZE;
+        let sealed_start = HEADER_SIZE + first * (SEGMENT_SIZE + SEGMENT_OVERHEAD);
+        let sealed_end =
+            sealed_size(size).min(HEADER_SIZE + (last + 1) * (SEGMENT_SIZE + SEGMENT_OVERHEAD));
+
+        let reader = self
+            .inner
+            .get_range(key, sealed_start, sealed_end - sealed_start)
+            .await?;
+
+        let mut index = first;
+        let final_index = last_segment(size);
+        let size = (SEGMENT_SIZE + SEGMENT_OVERHEAD) as usize;
+        let segments = Blocks::new(reader, std::iter::repeat(size), move |segment| {
+            index += 1;
+            open_segment(&cipher, index - 1, index - 1 == final_index, segment)
+        });
+
+        let skip = (start - first * SEGMENT_SIZE) as usize;
+        Ok(Box::new(segments.with_skip(skip).take(len)))
+    }
+
+    fn wrap_key(&self, id: &KeyId, key: &[u8; KEY_SIZE]) -> Result<Vec<u8>, Error> {
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let sealed = self
+            .master
+            .cipher()
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: key, aad: id })
+            .map_err(|_| Error::StorageError("Failed to wrap data key".to_string()))?;
+
+        Ok([self.master.id.as_slice(), &nonce, &sealed].concat())
+    }
+
+    fn unwrap_key(&self, id: &KeyId, wrapped: &[u8]) -> Result<[u8; KEY_SIZE], Error> {
+        let hex_id = HEXLOWER_PERMISSIVE.encode(id);
+        if wrapped.len() != MASTER_ID_SIZE + NONCE_SIZE + KEY_SIZE + TAG_SIZE {
+            return Err(Error::StorageError(format!(
+                "Data key {hex_id} is malformed"
+            )));
+        }
+
+        let (master_id, rest) = wrapped.split_at(MASTER_ID_SIZE);
+        let (nonce, sealed) = rest.split_at(NONCE_SIZE);
+
+        let Some(master) = std::iter::once(&self.master)
+            .chain(self.previous.iter())
+            .find(|m| m.id == master_id)
+        else {
+            return Err(Error::StorageError(format!(
+                "Data key {hex_id} is wrapped by unknown master key {}",
+                HEXLOWER_PERMISSIVE.encode(master_id)
+            )));
+        };
+
+        master
+            .cipher()
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: id,
+                },
+            )
+            .ok()
+            .and_then(|k| <[u8; KEY_SIZE]>::try_from(k).ok())
+            .ok_or_else(|| Error::StorageError(format!("Failed to unwrap data key {hex_id}")))
+    }
+}
+
+impl<S: Storage> Storage for EncryptedStorage<S> {
+    async fn put(
+        &self,
+        key: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+        size: u64,
+    ) -> Result<(), Error> {
+        let previous = self.key_id(key).await.ok().flatten();
+
+        let id: KeyId = rand::random();
+        let secret: [u8; KEY_SIZE] = rand::random();
+        let wrapped = self.wrap_key(&id, &secret)?;
+        self.inner
+            .put(
+                &data_key(&id),
+                &mut wrapped.as_slice(),
+                wrapped.len() as u64,
+            )
+            .await?;
+
+        let mut header = [MAGIC.as_slice(), &id].concat();
+        let cipher = XChaCha20Poly1305::new(&secret.into());
+        if size == 0 {
+            // Empty objects still get a final segment, so cutting a longer
+            // object back to its header does not pass for an empty one
+            header.extend(seal_segment(&cipher, 0, true, &[])?);
+        }
+
+        let mut index = 0;
+        let final_index = last_segment(size);
+        let sizes = std::iter::repeat(SEGMENT_SIZE as usize);
+        let mut sealed = Blocks::new(reader.take(size), sizes, move |segment| {
+            index += 1;
+            seal_segment(&cipher, index - 1, index - 1 == final_index, segment)
+        })
+        .with_prefix(header);
+
+        if let Err(e) = self.inner.put(key, &mut sealed, sealed_size(size)).await {
+            self.delete_key(&id).await.ok();
+            return Err(e);
+        }
+
+        if let Some(previous) = previous {
+            self.delete_key(&previous).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn append(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        let Some(size) = self.plain_size(key).await? else {
+            return Err(not_found(key));
+        };
+
+        self.write_at(key, size, data).await
+    }
+
+    async fn write_at(&self, key: &str, offset: u64, data: &[u8]) -> Result<(), Error> {
+        let Some(size) = self.plain_size(key).await? else {
+            return Err(not_found(key));
+        };
+
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let Some(end) = offset.checked_add(data.len() as u64) else {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+        };
+
+        // Rewrite every segment from the first one touched (or the current
+        // final one, if writing past it) through the last one touched
+        let first = (offset.min(size) / SEGMENT_SIZE).min(last_segment(size));
+        let start = first * SEGMENT_SIZE;
+        let existing_end = size.min((end - 1) / SEGMENT_SIZE * SEGMENT_SIZE + SEGMENT_SIZE);
+
+        let cipher = self.cipher(key).await?;
+        let mut plain = vec![];
+        self.open_range(
+            key,
+            cipher.clone(),
+            size,
+            start,
+            existing_end.saturating_sub(start),
+        )
+        .await?
+        .read_to_end(&mut plain)
+        .await?;
+
+        plain.resize(plain.len().max((end - start) as usize), 0);
+        plain[(offset - start) as usize..(end - start) as usize].copy_from_slice(data);
+
+        let final_index = last_segment(size.max(end));
+        let mut sealed = vec![];
+        for (i, segment) in plain.chunks(SEGMENT_SIZE as usize).enumerate() {
+            let index = first + i as u64;
+            sealed.extend(seal_segment(&cipher, index, index == final_index, segment)?);
+        }
+
+        let sealed_start = HEADER_SIZE + first * (SEGMENT_SIZE + SEGMENT_OVERHEAD);
+        self.inner.write_at(key, sealed_start, &sealed).await
+    }
+
+    async fn get_range(&self, key: &str, start: u64, len: u64) -> Result<ObjectReader, Error> {
+        let cipher = self.cipher(key).await?;
+        let Some(size) = self.plain_size(key).await? else {
+            return Err(not_found(key));
+        };
+
+        self.open_range(key, cipher, size, start, len).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        let replaced = self.key_id(to).await.ok().flatten();
+        self.inner.rename(from, to).await?;
+
+        if let Some(replaced) = replaced {
+            self.delete_key(&replaced).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        let id = self.key_id(key).await.ok().flatten();
+        self.inner.delete(key).await?;
+
+        if let Some(id) = id {
+            self.delete_key(&id).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Error> {
+        self.inner.list(dir).await
+    }
+
+    async fn stat(&self, key: &str) -> Result<Option<ObjectMeta>, Error> {
+        let Some(mut meta) = self.inner.stat(key).await? else {
+            return Ok(None);
+        };
+
+        meta.size = plain_size(meta.size)
+            .ok_or_else(|| Error::StorageError(format!("{key} has invalid encrypted size")))?;
+
+        Ok(Some(meta))
+    }
+
+    async fn rotate_keys(&self) -> Result<Option<u64>, Error> {
+        let mut rewrapped = 0;
+        for name in self.inner.list(KEY_DIR).await? {
+            let Some(id) = HEXLOWER_PERMISSIVE
+                .decode(name.as_bytes())
+                .ok()
+                .and_then(|id| KeyId::try_from(id).ok())
+            else {
+                continue;
+            };
+
+            // Held from reading the key to rewriting it, so a key deleted
+            // along with its object meanwhile is not written back
+            let _lock = self.key_lock.lock().await;
+
+            let key = data_key(&id);
+            let wrapped = match self.read_all(&key).await {
+                Err(Error::IoError(e)) if e.kind() == io::ErrorKind::NotFound => continue,
+                wrapped => wrapped?,
+            };
+            if wrapped.starts_with(&self.master.id) {
+                continue;
+            }
+
+            let secret = self.unwrap_key(&id, &wrapped)?;
+            let wrapped = self.wrap_key(&id, &secret)?;
+            self.inner
+                .put(&key, &mut wrapped.as_slice(), wrapped.len() as u64)
+                .await?;
+
+            rewrapped += 1;
+        }
+
+        Ok(Some(rewrapped))
+    }
+}
+
+/// A sealed segment is a random nonce followed by the ciphertext and tag.
+/// The segment index and whether it is the final segment are associated
+/// data, so segments cannot be reordered or dropped from the end.
+fn seal_segment(
+    cipher: &XChaCha20Poly1305,
+    index: u64,
+    last: bool,
+    data: &[u8],
+) -> io::Result<Vec<u8>> {
+    let nonce: [u8; NONCE_SIZE] = rand::random();
+    let aad = segment_aad(index, last);
+    let sealed = cipher
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: data,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| io::Error::other(format!("Failed to seal segment {index}")))?;
+
+    Ok([nonce.as_slice(), &sealed].concat())
+}
+
+fn open_segment(
+    cipher: &XChaCha20Poly1305,
+    index: u64,
+    last: bool,
+    data: &[u8],
+) -> io::Result<Vec<u8>> {
+    if data.len() < SEGMENT_OVERHEAD as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Encrypted segment {index} is truncated"),
+        ));
+    }
+
+    let (nonce, sealed) = data.split_at(NONCE_SIZE);
+    let aad = segment_aad(index, last);
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: sealed,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Encrypted segment {index} failed authentication"),
+            )
+        })
+}
+
+fn segment_aad(index: u64, last: bool) -> [u8; 9] {
+    let mut aad = [0; 9];
+    aad[..8].copy_from_slice(&index.to_be_bytes());
+    aad[8] = last as u8;
+    aad
+}
+
+fn last_segment(size: u64) -> u64 {
+    size.saturating_sub(1) / SEGMENT_SIZE
+}
+
+fn sealed_size(size: u64) -> u64 {
+    let segments = size.div_ceil(SEGMENT_SIZE).max(1);
+    HEADER_SIZE + size + segments * SEGMENT_OVERHEAD
+}
+
+fn plain_size(sealed: u64) -> Option<u64> {
+    let body = sealed.checked_sub(HEADER_SIZE)?;
+    let full = body / (SEGMENT_SIZE + SEGMENT_OVERHEAD);
+    let rest = body % (SEGMENT_SIZE + SEGMENT_OVERHEAD);
+
+    match rest {
+        0 if full > 0 => Some(full * SEGMENT_SIZE),
+        SEGMENT_OVERHEAD if full == 0 => Some(0),
+        rest if rest > SEGMENT_OVERHEAD => Some(full * SEGMENT_SIZE + rest - SEGMENT_OVERHEAD),
+        _ => None,
+    }
+}
+
+fn data_key(id: &KeyId) -> String {
+    format!("{KEY_DIR}/{}", HEXLOWER_PERMISSIVE.encode(id))
+}
+
+fn not_found(key: &str) -> Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("No such object {key}")).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HEADER_SIZE, SEGMENT_SIZE, plain_size, sealed_size};
+
+    #[test]
+    fn sealed_sizes() {
+        for size in [
+            0,
+            1,
+            SEGMENT_SIZE - 1,
+            SEGMENT_SIZE,
+            SEGMENT_SIZE + 1,
+            10 * SEGMENT_SIZE + 17,
+        ] {
+            assert_eq!(plain_size(sealed_size(size)), Some(size));
+        }
+
+        assert_eq!(plain_size(3), None);
+        assert_eq!(plain_size(HEADER_SIZE), None);
+        assert_eq!(plain_size(HEADER_SIZE + 10), None);
+    }
+}
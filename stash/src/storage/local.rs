@@ -0,0 +1,211 @@
+use std::{ffi::OsString, io::SeekFrom, path::PathBuf};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use super::{ObjectMeta, ObjectReader, Storage};
+use crate::Error;
+
+const LAYOUT_FILE: &str = "layout";
+const LAYOUT: &str = "fanout-2x2";
+/// Directories written by servers that predate the layout file.
+const FLAT_DIRS: [&str; 5] = ["files", "blobs", "chunks", "keys", "quarantine"];
+
+/// Directory tree under the server root. Objects are fanned out by the
+/// first four characters of their name, so `files/<hash>` is stored at
+/// `files/ab/cd/<hash>`. The layout is recorded in `<root>/layout`.
+#[derive(Clone, Debug)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    /// Opens the tree at `root`, migrating a flat layout written by older
+    /// servers and refusing layouts this version does not understand.
+    pub async fn open(root: PathBuf) -> Result<Self, Error> {
+        let storage = Self { root };
+        let layout_path = storage.root.join(LAYOUT_FILE);
+
+        match tokio::fs::read_to_string(&layout_path).await {
+            Ok(layout) if layout.trim() == LAYOUT => {}
+            Ok(layout) => {
+                return Err(Error::StorageError(format!(
+                    "Unsupported storage layout {:?} in {}",
+                    layout.trim(),
+                    layout_path.display()
+                )));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                storage.migrate().await?;
+
+                let tmp_path = storage.root.join(format!("{LAYOUT_FILE}.tmp"));
+                tokio::fs::write(&tmp_path, format!("{LAYOUT}\n")).await?;
+                tokio::fs::rename(&tmp_path, &layout_path).await?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(storage)
+    }
+
+    async fn migrate(&self) -> Result<(), Error> {
+        for dir_name in FLAT_DIRS {
+            let mut entries = match tokio::fs::read_dir(self.root.join(dir_name)).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut moved = 0;
+            while let Some(entry) = entries.next_entry().await? {
+                if !entry.file_type().await?.is_file() {
+                    continue;
+                }
+
+                let name = entry.file_name().to_string_lossy().to_string();
+                let path = self.create_path(&format!("{dir_name}/{name}")).await?;
+                tokio::fs::rename(entry.path(), path).await?;
+                moved += 1;
+            }
+
+            if moved > 0 {
+                tracing::info!(dir = dir_name, moved, "migrate_storage_layout");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let Some((dir, name)) = key.rsplit_once('/') else {
+            return self.root.join(key);
+        };
+
+        let mut fanout = name.chars().chain(std::iter::repeat('_'));
+        let outer: String = fanout.by_ref().take(2).collect();
+        let inner: String = fanout.take(2).collect();
+
+        self.root.join(dir).join(outer).join(inner).join(name)
+    }
+
+    async fn create_path(&self, key: &str) -> Result<PathBuf, Error> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        Ok(path)
+    }
+}
+
+impl Storage for LocalStorage {
+    async fn put(
+        &self,
+        key: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+        size: u64,
+    ) -> Result<(), Error> {
+        let path = self.create_path(key).await?;
+        let tmp_path = PathBuf::from({
+            let mut tmp_path = OsString::from(path.as_os_str());
+            tmp_path.push(".tmp");
+            tmp_path
+        });
+
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        let copied = match tokio::io::copy(&mut reader.take(size), &mut file).await {
+            Ok(n) if n == size => file.flush().await,
+            Ok(_) => Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = copied {
+            tokio::fs::remove_file(&tmp_path).await.ok();
+            return Err(e.into());
+        }
+
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+
+    async fn append(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        let mut file = tokio::fs::File::options()
+            .append(true)
+            .open(self.path(key))
+            .await?;
+
+        file.write_all(data).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn write_at(&self, key: &str, offset: u64, data: &[u8]) -> Result<(), Error> {
+        let mut file = tokio::fs::File::options()
+            .write(true)
+            .open(self.path(key))
+            .await?;
+
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn get_range(&self, key: &str, start: u64, len: u64) -> Result<ObjectReader, Error> {
+        let mut file = tokio::fs::File::open(self.path(key)).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+
+        Ok(Box::new(file.take(len)))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        let to = self.create_path(to).await?;
+        tokio::fs::rename(self.path(from), to).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            rsp => Ok(rsp?),
+        }
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Error> {
+        let mut dirs = vec![(self.root.join(dir), 0)];
+        let mut names = vec![];
+
+        while let Some((dir, depth)) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                entries => entries?,
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                if depth < 2 && file_type.is_dir() {
+                    dirs.push((entry.path(), depth + 1));
+                } else if depth == 2 && file_type.is_file() {
+                    names.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+
+        Ok(names)
+    }
+
+    async fn stat(&self, key: &str) -> Result<Option<ObjectMeta>, Error> {
+        let meta = match tokio::fs::metadata(self.path(key)).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            meta => meta?,
+        };
+
+        if !meta.is_file() {
+            return Ok(None);
+        }
+
+        Ok(Some(ObjectMeta {
+            size: meta.len(),
+            modified: meta.modified()?,
+        }))
+    }
+}
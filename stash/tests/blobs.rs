@@ -1,6 +1,6 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
 
-use stash::{Response, Tag};
+use stash::{ContentHash, Failure, Response, Tag};
 use util::{ClientServer, TestInfra};
 
 mod util;
@@ -17,14 +17,14 @@ async fn blob_management() {
     let blob = blob.unwrap();
     assert_eq!(blob.size, 0);
 
-    let blob_name = &blob.name.to_string();
+    let blob_name = &blob.name;
 
     let blob2 = client.describe_blob(blob_name.clone()).await.unwrap();
     assert!(matches!(blob2, Response::Ok(_)));
     assert_eq!(blob, blob2.unwrap());
 
     let blob = client
-        .append_blob(blob_name.clone(), b"hello".to_vec())
+        .append_blob(blob_name.clone(), 0, b"hello".to_vec())
         .await
         .unwrap();
     assert!(matches!(blob, Response::Ok(_)));
@@ -33,7 +33,7 @@ async fn blob_management() {
     assert_eq!(blob.size, 5);
 
     let blob = client
-        .append_blob(blob_name.clone(), b" world".to_vec())
+        .append_blob(blob_name.clone(), 5, b" world".to_vec())
         .await
         .unwrap();
     assert!(matches!(blob, Response::Ok(_)));
@@ -41,18 +41,72 @@ async fn blob_management() {
     let blob = blob.unwrap();
     assert_eq!(blob.size, 11);
 
+    let rsp = client
+        .append_blob(blob_name.clone(), 5, b" world".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("Blob is at offset 11, not 5".to_string())
+    );
+
     let blob = client.describe_blob(blob_name.clone()).await.unwrap();
     assert!(matches!(blob, Response::Ok(_)));
 
     let blob = blob.unwrap();
     assert_eq!(blob.size, 11);
 
+    let tags = vec![Tag::from_str("t1").unwrap(), Tag::from_str("t2").unwrap()];
+    let hash: ContentHash = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        .parse()
+        .unwrap();
+    let wrong_hash: ContentHash = "0".repeat(64).parse().unwrap();
+
+    let rsp = client
+        .commit_blob(
+            blob_name.clone(),
+            "test-file".to_string(),
+            tags.clone(),
+            false,
+            None,
+            Some(10),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("Blob size 11 does not match expected size 10".to_string())
+    );
+
+    let rsp = client
+        .commit_blob(
+            blob_name.clone(),
+            "test-file".to_string(),
+            tags.clone(),
+            false,
+            Some(wrong_hash.clone()),
+            Some(11),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict(format!(
+            "Blob hash {hash} does not match expected hash {wrong_hash}"
+        ))
+    );
+
+    let blob = client.describe_blob(blob_name.clone()).await.unwrap();
+    assert_eq!(blob.unwrap().size, 11);
+
     let file = client
         .commit_blob(
             blob_name.clone(),
             "test-file".to_string(),
-            vec![Tag::from_str("t1").unwrap(), Tag::from_str("t2").unwrap()],
+            tags,
             false,
+            Some(hash.clone()),
+            Some(11),
         )
         .await
         .unwrap();
@@ -71,9 +125,161 @@ async fn blob_management() {
 
     let rsp = client.describe_blob(blob_name.clone()).await.unwrap();
     assert!(matches!(rsp, Response::Err(_)));
-    assert_eq!(rsp.err(), "No such blob");
+    assert_eq!(rsp.err(), Failure::NotFound("No such blob".to_string()));
 
     let data = client.download(file.hash, 0, 11).await.unwrap();
     assert!(matches!(data, Response::Ok(_)));
     assert_eq!(data.unwrap(), b"hello world".to_vec());
 }
+
+#[tokio::test]
+async fn blob_write() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = &client_server.client;
+    let admin = client_server.admin().await;
+
+    let blob = client.create_blob().await.unwrap().unwrap().name;
+
+    let (world, hello) = tokio::join!(
+        client.write_blob(blob.clone(), 6, b"world".to_vec()),
+        client.write_blob(blob.clone(), 0, b"hello ".to_vec()),
+    );
+    assert!(matches!(world.unwrap(), Response::Ok(_)));
+    assert!(matches!(hello.unwrap(), Response::Ok(_)));
+
+    let rsp = client
+        .write_blob(blob.clone(), 0, b"jello".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(rsp.unwrap().size, 11);
+
+    let rsp = client
+        .write_blob(blob.clone(), 0, b"hello".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(rsp.unwrap().size, 11);
+
+    let rsp = client
+        .write_blob(blob.clone(), u64::MAX, b"overflow".to_vec())
+        .await
+        .unwrap();
+    assert!(matches!(rsp.err(), Failure::Quota(_)));
+
+    let rsp = admin
+        .write_blob(blob.clone(), 0, b"garbage".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Unauthorized("Blob belongs to another node".to_string())
+    );
+
+    let hash: ContentHash = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        .parse()
+        .unwrap();
+
+    let file = client
+        .commit_blob(
+            blob,
+            "test-file".to_string(),
+            vec![Tag::from_str("t1").unwrap()],
+            false,
+            Some(hash.clone()),
+            Some(11),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(file.hash, hash);
+
+    let data = client.download(file.hash, 0, 11).await.unwrap().unwrap();
+    assert_eq!(data, b"hello world".to_vec());
+}
+
+#[tokio::test]
+async fn blob_gc() {
+    let infra = TestInfra::new().await;
+    let client_server =
+        ClientServer::configure(infra, |server| server.with_blob_ttl(Duration::from_secs(2))).await;
+    let client = client_server.client;
+
+    let idle = client.create_blob().await.unwrap().unwrap().name;
+    client
+        .append_blob(idle.clone(), 0, b"hello".to_vec())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let report = client.gc_blobs().await.unwrap().unwrap();
+    assert!(report.removed.is_empty());
+    assert_eq!(report.freed, 0);
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let active = client.create_blob().await.unwrap().unwrap().name;
+
+    let report = client.gc_blobs().await.unwrap().unwrap();
+    assert_eq!(report.removed, vec![idle.to_string()]);
+    assert_eq!(report.freed, 5);
+
+    let rsp = client.describe_blob(idle).await.unwrap();
+    assert_eq!(rsp.err(), Failure::NotFound("No such blob".to_string()));
+
+    let rsp = client.describe_blob(active).await.unwrap();
+    assert!(matches!(rsp, Response::Ok(_)));
+}
+
+#[tokio::test]
+async fn blob_ownership() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = &client_server.client;
+    let admin = client_server.admin().await;
+
+    let blob = client.create_blob().await.unwrap().unwrap().name;
+    client
+        .append_blob(blob.clone(), 0, b"hello".to_vec())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let unauthorized = Failure::Unauthorized("Blob belongs to another node".to_string());
+
+    let rsp = admin.describe_blob(blob.clone()).await.unwrap();
+    assert_eq!(rsp.err(), unauthorized);
+
+    let rsp = admin
+        .append_blob(blob.clone(), 5, b"garbage".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(rsp.err(), unauthorized);
+
+    let rsp = admin
+        .commit_blob(
+            blob.clone(),
+            "stolen".to_string(),
+            vec![Tag::from_str("t1").unwrap()],
+            false,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(rsp.err(), unauthorized);
+
+    let rsp = client.list_blobs().await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Unauthorized("Admin access required".to_string())
+    );
+
+    let blobs = admin.list_blobs().await.unwrap().unwrap();
+    assert_eq!(blobs.len(), 1);
+    assert_eq!(blobs[0].name, blob);
+    assert_eq!(blobs[0].owner, client_server.client_sk.public().to_string());
+    assert_eq!(blobs[0].size, 5);
+
+    let blob = client.describe_blob(blob).await.unwrap().unwrap();
+    assert_eq!(blob.size, 5);
+}
@@ -0,0 +1,222 @@
+use std::{
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use stash::{Error, LocalStorage, ObjectMeta, ObjectReader, Response, Storage, Tag};
+use tokio::io::AsyncRead;
+use util::{ClientServer, TestInfra, create_file, read_all};
+
+mod util;
+
+#[tokio::test]
+async fn chunked_storage() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::configure(infra, |s| s.with_chunking(true)).await;
+    let client = &client_server.client;
+
+    let original = data(10_000_000, 1);
+    let mut edited = original.clone();
+    edited.splice(5_000_000..5_000_010, [0u8; 100]);
+
+    let file1 = create_file(client, "f1", &["test"], false, &original).await;
+    let file2 = create_file(client, "f2", &["test"], false, &edited).await;
+
+    assert!(client_server.infra.files().await.is_empty());
+    assert!(client_server.infra.blobs().await.is_empty());
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.files, 2);
+    assert_eq!(stats.contents, 2);
+    assert_eq!(stats.logical_size, (original.len() + edited.len()) as u64);
+    assert_eq!(stats.content_size, stats.logical_size);
+    assert!(stats.stored_size < stats.content_size * 2 / 3);
+    assert_eq!(
+        stats.chunks as usize,
+        client_server.infra.objects("chunks").await.len()
+    );
+
+    assert_eq!(read_all(client, &file1).await, original);
+    assert_eq!(read_all(client, &file2).await, edited);
+
+    let data = client
+        .download(file2.hash.clone(), 4_999_000, 2_000)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(data, edited[4_999_000..5_001_000].to_vec());
+
+    client.delete("f1".to_string()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.contents, 1);
+    assert_eq!(stats.stored_size, edited.len() as u64);
+    assert_eq!(
+        stats.chunks as usize,
+        client_server.infra.objects("chunks").await.len()
+    );
+    assert_eq!(read_all(client, &file2).await, edited);
+
+    client.delete("f2".to_string()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.chunks, 0);
+    assert_eq!(stats.stored_size, 0);
+    assert!(client_server.infra.objects("chunks").await.is_empty());
+}
+
+#[tokio::test]
+async fn concurrent_commits() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::configure(infra, |s| s.with_chunking(true)).await;
+    let client = &client_server.client;
+
+    let a = data(3_000_000, 1);
+    let b = data(3_000_000, 2);
+    let c = data(3_000_000, 3);
+
+    create_file(client, "f1", &["test"], false, &[a, b.clone()].concat()).await;
+
+    // f2 shares chunks with f1, which is purged while f2 is being stored
+    let edited = [b, c].concat();
+    let (file2, _) = tokio::join!(
+        create_file(client, "f2", &["test"], false, &edited),
+        async {
+            client.delete("f1".to_string()).await.unwrap().unwrap();
+            client.purge_trash(None).await.unwrap().unwrap();
+        }
+    );
+
+    let (file3, file4) = tokio::join!(
+        create_file(client, "f3", &["test"], false, &edited),
+        create_file(client, "f4", &["test"], false, &edited),
+    );
+    assert_eq!(file3.hash, file2.hash);
+    assert_eq!(file4.hash, file2.hash);
+
+    client.purge_trash(None).await.unwrap().unwrap();
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.contents, 1);
+    assert_eq!(stats.stored_size, edited.len() as u64);
+    assert_eq!(
+        stats.chunks as usize,
+        client_server.infra.objects("chunks").await.len()
+    );
+    assert_eq!(read_all(client, &file2).await, edited);
+
+    let report = client.scrub(None, 1_000, false).await.unwrap().unwrap();
+    assert!(report.missing.is_empty());
+    assert!(report.corrupted.is_empty());
+}
+
+#[tokio::test]
+async fn failed_commit() {
+    let infra = TestInfra::new().await;
+    let local = LocalStorage::open(infra.root.clone()).await.unwrap();
+    let chunk_puts = Arc::new(AtomicUsize::new(3));
+    let storage = FlakyStorage {
+        inner: local,
+        chunk_puts: chunk_puts.clone(),
+    };
+    let client_server =
+        ClientServer::configure_storage(infra, storage, |s| s.with_chunking(true)).await;
+    let client = &client_server.client;
+
+    let content = data(10_000_000, 1);
+    let upload = async || {
+        client
+            .upload(
+                "f1".to_string(),
+                vec![Tag::from_str("test").unwrap()],
+                false,
+                content.len() as u64,
+                None,
+                content.as_slice(),
+            )
+            .await
+            .unwrap()
+    };
+
+    assert!(matches!(upload().await, Response::Err(_)));
+    assert!(client_server.infra.objects("chunks").await.is_empty());
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.contents, 0);
+    assert_eq!(stats.chunks, 0);
+
+    chunk_puts.store(usize::MAX, Ordering::SeqCst);
+    let file = upload().await.unwrap();
+    assert_eq!(read_all(client, &file).await, content);
+}
+
+/// Local storage that fails chunk writes once `chunk_puts` runs out.
+struct FlakyStorage {
+    inner: LocalStorage,
+    chunk_puts: Arc<AtomicUsize>,
+}
+
+impl Storage for FlakyStorage {
+    async fn put(
+        &self,
+        key: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+        size: u64,
+    ) -> Result<(), Error> {
+        if key.starts_with("chunks/")
+            && self
+                .chunk_puts
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_err()
+        {
+            return Err(std::io::Error::other("storage full").into());
+        }
+
+        self.inner.put(key, reader, size).await
+    }
+
+    async fn append(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.inner.append(key, data).await
+    }
+
+    async fn write_at(&self, key: &str, offset: u64, data: &[u8]) -> Result<(), Error> {
+        self.inner.write_at(key, offset, data).await
+    }
+
+    async fn get_range(&self, key: &str, start: u64, len: u64) -> Result<ObjectReader, Error> {
+        self.inner.get_range(key, start, len).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        self.inner.rename(from, to).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        self.inner.delete(key).await
+    }
+
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Error> {
+        self.inner.list(dir).await
+    }
+
+    async fn stat(&self, key: &str) -> Result<Option<ObjectMeta>, Error> {
+        self.inner.stat(key).await
+    }
+}
+
+fn data(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
@@ -0,0 +1,150 @@
+use stash::{CompressionPolicy, Failure};
+use util::{ClientServer, TestInfra, create_file, read_all};
+
+mod util;
+
+#[tokio::test]
+async fn compressed_storage() {
+    let infra = TestInfra::new().await;
+    let client_server =
+        ClientServer::configure(infra, |s| s.with_compression(CompressionPolicy::default())).await;
+    let client = &client_server.client;
+
+    let text = text(3_000_000);
+    let file = create_file(client, "f1", &["test"], false, &text).await;
+
+    assert_eq!(client_server.infra.files().await, vec![file.hash.clone()]);
+    assert!(client_server.infra.blobs().await.is_empty());
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.logical_size, text.len() as u64);
+    assert_eq!(stats.content_size, text.len() as u64);
+    assert!(stats.stored_size < stats.content_size / 4);
+
+    let stored = std::fs::read(client_server.infra.object_path("files", file.hash.as_str()));
+    assert_eq!(stored.unwrap().len() as u64, stats.stored_size);
+
+    assert_eq!(read_all(client, &file).await, text);
+
+    for (start, len) in [
+        (0, 10),
+        (1_048_000, 2_000),
+        (1_000_000, 1_500_000),
+        (2_999_990, 10),
+    ] {
+        let data = client
+            .download(file.hash.clone(), start, len)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(data, text[start as usize..(start + len) as usize].to_vec());
+    }
+
+    let rsp = client
+        .download(file.hash.clone(), 2_999_990, 11)
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::OutOfRange("Data index out of bounds".to_string())
+    );
+
+    let report = client.scrub(None, 10, false).await.unwrap().unwrap();
+    assert_eq!(report.checked, 1);
+    assert!(report.corrupted.is_empty());
+    assert!(report.missing.is_empty());
+    assert!(report.orphaned.is_empty());
+
+    let client_server = client_server.restart().await;
+    let client = &client_server.client;
+    assert_eq!(read_all(client, &file).await, text);
+
+    client.delete("f1".to_string()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.stored_size, 0);
+    assert!(client_server.infra.files().await.is_empty());
+}
+
+#[tokio::test]
+async fn incompressible_content() {
+    let infra = TestInfra::new().await;
+    let client_server =
+        ClientServer::configure(infra, |s| s.with_compression(CompressionPolicy::default())).await;
+    let client = &client_server.client;
+
+    let random = random(100_000);
+    let gzip = [b"\x1f\x8b".as_slice(), &text(100_000)].concat();
+    let small = text(1_000);
+
+    let mut size = 0;
+    for (name, content) in [("random", &random), ("gzip", &gzip), ("small", &small)] {
+        let file = create_file(client, name, &["test"], false, content).await;
+        assert_eq!(read_all(client, &file).await, *content);
+        size += content.len() as u64;
+    }
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.stored_size, size);
+    assert!(client_server.infra.blobs().await.is_empty());
+}
+
+#[tokio::test]
+async fn compression_tags() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::configure(infra, |s| {
+        s.with_compression(CompressionPolicy {
+            tags: vec!["logs".to_string()],
+            ..Default::default()
+        })
+    })
+    .await;
+    let client = &client_server.client;
+
+    let text = text(100_000);
+    let mut other = text.clone();
+    other[0] = b'#';
+
+    let logs = create_file(client, "logs", &["logs"], false, &text).await;
+    let stats = client.stats().await.unwrap().unwrap();
+    assert!(stats.stored_size < text.len() as u64 / 2);
+    let compressed = stats.stored_size;
+
+    let plain = create_file(client, "plain", &["test"], false, &other).await;
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.stored_size, compressed + other.len() as u64);
+
+    assert_eq!(read_all(client, &logs).await, text);
+    assert_eq!(read_all(client, &plain).await, other);
+}
+
+fn text(len: usize) -> Vec<u8> {
+    let words = ["stash", "blob", "chunk", "frame", "content", "tag", "file"];
+    let mut state = 1u64;
+    let mut text = Vec::with_capacity(len + 8);
+    while text.len() < len {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+        text.extend_from_slice(words[(state >> 33) as usize % words.len()].as_bytes());
+        text.push(if (state >> 20).is_multiple_of(12) {
+            b'\n'
+        } else {
+            b' '
+        });
+    }
+
+    text.truncate(len);
+    text
+}
+
+fn random(len: usize) -> Vec<u8> {
+    let mut state = 7u64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
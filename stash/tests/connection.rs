@@ -0,0 +1,62 @@
+use std::str::FromStr;
+
+use stash::{Response, Tag};
+use util::{ClientServer, TestInfra};
+
+mod util;
+
+#[tokio::test]
+async fn concurrent_requests() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = client_server.client;
+
+    let tag = Tag::from_str("test").unwrap();
+
+    for i in 0..20 {
+        let content = format!("file {i}");
+        client
+            .upload(
+                format!("f{i}"),
+                vec![tag.clone()],
+                false,
+                content.len() as u64,
+                None,
+                content.as_bytes(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    let files = client.list(tag, None).await.unwrap().unwrap();
+    assert_eq!(files.len(), 20);
+
+    let mut tasks = vec![];
+    for file in files.into_iter() {
+        let client = client.clone();
+        tasks.push(tokio::spawn(async move {
+            let desc = client.describe(file.name.clone()).await.unwrap().unwrap();
+            assert_eq!(desc.hash, file.hash);
+        }));
+    }
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn reconnect() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = client_server.client;
+
+    let rsp = client.tags().await.unwrap();
+    assert!(matches!(rsp, Response::Ok(_)));
+
+    client.close().await;
+
+    let rsp = client.tags().await.unwrap();
+    assert!(matches!(rsp, Response::Ok(_)));
+}
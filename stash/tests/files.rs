@@ -1,6 +1,7 @@
 use std::str::FromStr;
 
-use stash::{Client, File, Response, Tag};
+use stash::{Client, ContentHash, Error, Failure, File, Response, Tag};
+use tokio::io::AsyncReadExt;
 use util::{ClientServer, TestInfra};
 
 mod util;
@@ -56,7 +57,10 @@ async fn file_cas() {
     .await;
 
     assert!(matches!(file4, Response::Err(_)));
-    assert_eq!(file4.err(), "File already exists");
+    assert_eq!(
+        file4.err(),
+        Failure::Conflict("File already exists".to_string())
+    );
 
     assert_eq!(&file1.size, &file3.size);
     assert_eq!(&file1.hash, &file3.hash);
@@ -95,6 +99,7 @@ async fn file_cas() {
     assert_eq!(files, vec![file1.hash.clone(), file2.hash.clone()]);
 
     client.delete(file1.name.clone()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
 
     let mut files = client_server.infra.files().await;
     files.sort();
@@ -102,9 +107,13 @@ async fn file_cas() {
 
     let tags1 = client.describe(file1.name.clone()).await.unwrap();
     assert!(matches!(tags1, Response::Err(_)));
-    assert_eq!(tags1.err(), "No such file");
+    assert_eq!(tags1.err(), Failure::NotFound("No such file".to_string()));
+
+    let rsp = client.describe(file1.name.clone()).await.unwrap().res();
+    assert!(matches!(rsp, Err(Error::NotFound(_))));
 
     client.delete(file3.name.clone()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
 
     let mut files = client_server.infra.files().await;
     files.sort();
@@ -195,7 +204,10 @@ async fn file_replace() {
 
     let file3 = create_file(&client, "hello-1", vec![tag.clone()], false, b"world").await;
     assert!(matches!(file3, Response::Err(_)));
-    assert_eq!(file3.err(), "File already exists");
+    assert_eq!(
+        file3.err(),
+        Failure::Conflict("File already exists".to_string())
+    );
 
     let file3 = create_file(&client, "hello-1", vec![tag.clone()], true, b"world")
         .await
@@ -209,6 +221,376 @@ async fn file_replace() {
     assert_eq!(files, vec![file3, file2]);
 }
 
+#[tokio::test]
+async fn file_stream() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = client_server.client;
+
+    let tag = Tag::from_str("test").unwrap();
+    let content: Vec<u8> = (0..3_000_000).map(|i| (i % 251) as u8).collect();
+
+    let file = create_file(&client, "big", vec![tag], false, &content)
+        .await
+        .unwrap();
+
+    let mut stream = client
+        .stream(file.hash.clone(), 0, file.size)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stream.remaining(), content.len() as u64);
+
+    let mut data = vec![];
+    stream.read_to_end(&mut data).await.unwrap();
+    assert_eq!(data, content);
+    assert_eq!(stream.remaining(), 0);
+
+    let mut stream = client
+        .stream(file.hash.clone(), 1_000, 10)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut data = vec![];
+    stream.read_to_end(&mut data).await.unwrap();
+    assert_eq!(data, content[1_000..1_010].to_vec());
+
+    let rsp = client
+        .stream(file.hash.clone(), 1, file.size)
+        .await
+        .unwrap();
+    assert!(matches!(rsp, Response::Err(_)));
+    assert_eq!(
+        rsp.err(),
+        Failure::OutOfRange("Data index out of bounds".to_string())
+    );
+
+    let missing = "0".repeat(64).parse().unwrap();
+    let rsp = client.stream(missing, 0, 1).await.unwrap();
+    assert!(matches!(rsp, Response::Err(_)));
+    assert_eq!(rsp.err(), Failure::NotFound("No such file".to_string()));
+}
+
+#[tokio::test]
+async fn file_upload() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = client_server.client;
+
+    let tag = Tag::from_str("test").unwrap();
+    let content: Vec<u8> = (0..3_000_000).map(|i| (i % 251) as u8).collect();
+
+    let file = client
+        .upload(
+            "big".to_string(),
+            vec![tag.clone()],
+            false,
+            content.len() as u64,
+            None,
+            content.as_slice(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(file.name, "big");
+    assert_eq!(file.size, content.len() as u64);
+
+    let expected = create_file(&client, "big-2", vec![tag.clone()], false, &content)
+        .await
+        .unwrap();
+    assert_eq!(file.hash, expected.hash);
+
+    let mut data = vec![];
+    client
+        .stream(file.hash.clone(), 0, file.size)
+        .await
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut data)
+        .await
+        .unwrap();
+    assert_eq!(data, content);
+
+    let rsp = client
+        .upload(
+            "big".to_string(),
+            vec![tag.clone()],
+            false,
+            content.len() as u64,
+            None,
+            content.as_slice(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(rsp, Response::Err(_)));
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("File already exists".to_string())
+    );
+
+    let wrong = ContentHash::from_str(&"0".repeat(64)).unwrap();
+    let rsp = client
+        .upload(
+            "big".to_string(),
+            vec![tag.clone()],
+            true,
+            content.len() as u64,
+            Some(wrong.clone()),
+            content.as_slice(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict(format!(
+            "Upload hash {} does not match expected hash {wrong}",
+            file.hash
+        ))
+    );
+
+    let versions = client.versions("big".to_string()).await.unwrap().unwrap();
+    assert_eq!(versions.len(), 1);
+
+    let rsp = client
+        .upload(
+            "short".to_string(),
+            vec![tag.clone()],
+            false,
+            10,
+            None,
+            &b"hello"[..],
+        )
+        .await
+        .unwrap();
+    assert!(matches!(rsp, Response::Err(_)));
+    assert_eq!(
+        rsp.err(),
+        Failure::InvalidArgument("Upload ended before declared size".to_string())
+    );
+
+    let rsp = client
+        .upload(
+            "long".to_string(),
+            vec![tag.clone()],
+            false,
+            2,
+            None,
+            &b"hello"[..],
+        )
+        .await
+        .unwrap();
+    assert!(matches!(rsp, Response::Err(_)));
+    assert_eq!(
+        rsp.err(),
+        Failure::InvalidArgument("Upload exceeds declared size".to_string())
+    );
+
+    assert!(client_server.infra.blobs().await.is_empty());
+
+    let files = client.list(tag, None).await.unwrap().unwrap();
+    assert_eq!(files, vec![file, expected]);
+}
+
+#[tokio::test]
+async fn file_dedup() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = &client_server.client;
+
+    let tag = Tag::from_str("test").unwrap();
+    let file1 = create_file(client, "hello-1", vec![tag.clone()], false, b"world")
+        .await
+        .unwrap();
+
+    let missing: ContentHash = "0".repeat(64).parse().unwrap();
+    assert!(
+        client
+            .has_content(file1.hash.clone())
+            .await
+            .unwrap()
+            .unwrap()
+    );
+    assert!(!client.has_content(missing.clone()).await.unwrap().unwrap());
+
+    let file2 = client
+        .commit_hash(
+            file1.hash.clone(),
+            "hello-2".to_string(),
+            vec![tag.clone()],
+            false,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(file2.name, "hello-2");
+    assert_eq!(file2.hash, file1.hash);
+    assert_eq!(file2.size, file1.size);
+
+    let rsp = client
+        .commit_hash(
+            file1.hash.clone(),
+            "hello-2".to_string(),
+            vec![tag.clone()],
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("File already exists".to_string())
+    );
+
+    let rsp = client
+        .commit_hash(missing, "hello-3".to_string(), vec![tag.clone()], false)
+        .await
+        .unwrap();
+    assert_eq!(rsp.err(), Failure::NotFound("No such content".to_string()));
+
+    assert_eq!(client_server.infra.files().await, vec![file1.hash.clone()]);
+
+    client.delete("hello-1".to_string()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
+    assert_eq!(client_server.infra.files().await, vec![file1.hash.clone()]);
+
+    client.delete("hello-2".to_string()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
+    assert!(client_server.infra.files().await.is_empty());
+}
+
+#[tokio::test]
+async fn file_rename() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = client_server.client;
+
+    let tag = Tag::from_str("test").unwrap();
+
+    let file1 = create_file(&client, "a", vec![tag.clone()], false, b"one")
+        .await
+        .unwrap();
+    let file2 = create_file(&client, "a", vec![tag.clone()], true, b"two")
+        .await
+        .unwrap();
+    let file3 = create_file(&client, "b", vec![tag.clone()], false, b"three")
+        .await
+        .unwrap();
+
+    let rsp = client
+        .rename("a".to_string(), "b".to_string(), false)
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("File already exists".to_string())
+    );
+
+    let rsp = client
+        .rename("a".to_string(), "a".to_string(), true)
+        .await
+        .unwrap();
+    assert!(matches!(rsp, Response::Err(Failure::InvalidArgument(_))));
+
+    let rsp = client
+        .rename("missing".to_string(), "c".to_string(), false)
+        .await
+        .unwrap();
+    assert_eq!(rsp.err(), Failure::NotFound("No such file".to_string()));
+
+    let renamed = client
+        .rename("a".to_string(), "c".to_string(), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(renamed.name, "c");
+    assert_eq!(renamed.hash, file2.hash);
+
+    let rsp = client.describe("a".to_string()).await.unwrap();
+    assert_eq!(rsp.err(), Failure::NotFound("No such file".to_string()));
+
+    let versions = client.versions("c".to_string()).await.unwrap().unwrap();
+    let hashes: Vec<_> = versions.iter().map(|v| v.hash.clone()).collect();
+    assert_eq!(hashes, vec![file2.hash.clone(), file1.hash.clone()]);
+
+    client
+        .rename("c".to_string(), "b".to_string(), true)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let files = client.list(tag, None).await.unwrap().unwrap();
+    let names: Vec<_> = files.iter().map(|f| (f.name.as_str(), &f.hash)).collect();
+    assert_eq!(names, vec![("b", &file2.hash)]);
+
+    let trash = client.trash().await.unwrap().unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].name, "b");
+    assert_eq!(trash[0].hash, file3.hash);
+}
+
+#[tokio::test]
+async fn file_copy() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = client_server.client;
+
+    let tag1 = Tag::from_str("one").unwrap();
+    let tag2 = Tag::from_str("two").unwrap();
+
+    let file = create_file(
+        &client,
+        "a",
+        vec![tag1.clone(), tag2.clone()],
+        false,
+        b"hello",
+    )
+    .await
+    .unwrap();
+
+    let copy = client
+        .copy("a".to_string(), "b".to_string(), None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(copy.name, "b");
+    assert_eq!(copy.hash, file.hash);
+
+    let desc = client.describe("b".to_string()).await.unwrap().unwrap();
+    assert_eq!(desc.tags, vec!["one".to_string(), "two".to_string()]);
+
+    client
+        .copy("a".to_string(), "c".to_string(), Some(vec![tag2]))
+        .await
+        .unwrap()
+        .unwrap();
+    let desc = client.describe("c".to_string()).await.unwrap().unwrap();
+    assert_eq!(desc.tags, vec!["two".to_string()]);
+
+    let rsp = client
+        .copy("a".to_string(), "b".to_string(), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("File already exists".to_string())
+    );
+
+    let rsp = client
+        .copy("a".to_string(), "d".to_string(), Some(vec![]))
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::InvalidArgument("At least one tag is required".to_string())
+    );
+
+    assert_eq!(client_server.infra.files().await, vec![file.hash]);
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.files, 3);
+    assert_eq!(stats.contents, 1);
+}
+
 async fn create_file(
     client: &Client,
     name: &str,
@@ -218,13 +600,13 @@ async fn create_file(
 ) -> Response<File> {
     let blob = client.create_blob().await.unwrap().unwrap();
     let blob = client
-        .append_blob(blob.name, content.to_vec())
+        .append_blob(blob.name, 0, content.to_vec())
         .await
         .unwrap()
         .unwrap();
 
     client
-        .commit_blob(blob.name, name.to_string(), tags, replace)
+        .commit_blob(blob.name, name.to_string(), tags, replace, None, None)
         .await
         .unwrap()
 }
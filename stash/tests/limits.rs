@@ -0,0 +1,107 @@
+use std::str::FromStr;
+
+use stash::{Cmd, Failure, Limits, Response, Tag};
+use util::{ClientServer, TestInfra};
+
+mod util;
+
+fn limits() -> Limits {
+    Limits {
+        max_request_size: 1_000,
+        max_download_len: 4,
+        max_append_size: 8,
+        max_blob_size: 12,
+        max_concurrent_requests: 64,
+    }
+}
+
+#[tokio::test]
+async fn blob_limits() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::with_limits(infra, limits()).await;
+    let client = client_server.client;
+
+    let blob = client.create_blob().await.unwrap().unwrap().name;
+
+    let rsp = client
+        .append_blob(blob.clone(), 0, vec![0; 9])
+        .await
+        .unwrap();
+    assert!(matches!(rsp.err(), Failure::Quota(_)));
+
+    client
+        .append_blob(blob.clone(), 0, vec![0; 8])
+        .await
+        .unwrap()
+        .unwrap();
+
+    let rsp = client
+        .append_blob(blob.clone(), 8, vec![0; 8])
+        .await
+        .unwrap();
+    assert!(matches!(rsp.err(), Failure::Quota(_)));
+
+    let blob = client.describe_blob(blob).await.unwrap().unwrap();
+    assert_eq!(blob.size, 8);
+}
+
+#[tokio::test]
+async fn transfer_limits() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::with_limits(infra, limits()).await;
+    let client = client_server.client;
+
+    let tag = Tag::from_str("test").unwrap();
+
+    let content = b"hello world, again";
+    let rsp = client
+        .upload(
+            "big".to_string(),
+            vec![tag.clone()],
+            false,
+            content.len() as u64,
+            None,
+            &content[..],
+        )
+        .await
+        .unwrap();
+    assert!(matches!(rsp.err(), Failure::Quota(_)));
+
+    let content = b"hello world";
+    let file = client
+        .upload(
+            "small".to_string(),
+            vec![tag],
+            false,
+            content.len() as u64,
+            None,
+            &content[..],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+    let rsp = client.download(file.hash.clone(), 0, 5).await.unwrap();
+    assert!(matches!(rsp.err(), Failure::Quota(_)));
+
+    let data = client.download(file.hash, 0, 4).await.unwrap().unwrap();
+    assert_eq!(data, b"hell");
+}
+
+#[tokio::test]
+async fn request_size() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::with_limits(infra, limits()).await;
+
+    let cmd = Cmd::Search {
+        tag: "test".to_string(),
+        term: "x".repeat(2_000),
+    };
+    let data = bincode::encode_to_vec(&cmd, bincode::config::standard()).unwrap();
+
+    let rsp = client_server.raw_request(&data).await.unwrap();
+    let rsp: Response<()> = bincode::decode_from_slice(&rsp, bincode::config::standard())
+        .unwrap()
+        .0;
+    assert!(matches!(rsp.err(), Failure::Quota(_)));
+}
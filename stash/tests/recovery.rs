@@ -0,0 +1,97 @@
+use sqlx::SqlitePool;
+use stash::BlobId;
+use util::{ClientServer, TestInfra, create_file};
+
+mod util;
+
+#[tokio::test]
+async fn reconcile_content() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+
+    let file1 = create_file(&client_server.client, "f1", &["test"], false, b"hello").await;
+    let file2 = create_file(&client_server.client, "f2", &["test"], false, b"world").await;
+
+    let infra = &client_server.infra;
+    std::fs::write(infra.object_path("files", &"1".repeat(64)), b"orphan").unwrap();
+    std::fs::write(infra.object_path("files", "junk"), b"junk").unwrap();
+    std::fs::write(infra.object_path("chunks", &"4".repeat(64)), b"chunk").unwrap();
+    std::fs::remove_file(infra.object_path("files", file2.hash.as_str())).unwrap();
+
+    let client_server = client_server.restart().await;
+    let client = &client_server.client;
+
+    assert_eq!(client_server.infra.files().await, vec![file1.hash.clone()]);
+    assert_eq!(
+        client_server.infra.objects("quarantine").await,
+        vec!["1".repeat(64), "4".repeat(64), "junk".to_string()]
+    );
+    assert!(client_server.infra.objects("chunks").await.is_empty());
+
+    let desc = client.describe("f2".to_string()).await.unwrap().unwrap();
+    assert_eq!(desc.hash, file2.hash);
+
+    let data = client.download(file1.hash, 0, 5).await.unwrap().unwrap();
+    assert_eq!(data, b"hello");
+
+    let report = client.scrub(None, 10, false).await.unwrap().unwrap();
+    assert_eq!(report.missing, vec![file2.hash]);
+    assert!(report.orphaned.is_empty());
+}
+
+#[tokio::test]
+async fn replay_journal() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+
+    let file = create_file(&client_server.client, "f1", &["test"], false, b"hello").await;
+
+    let root = client_server.infra.root.clone();
+    let db = SqlitePool::connect(&format!("sqlite://{}", root.join("server.db").display()))
+        .await
+        .unwrap();
+
+    let pending = BlobId::new();
+    journal(&db, "commit", &"2".repeat(64), Some(&pending)).await;
+    let pending_path = client_server.infra.object_path("files", &"2".repeat(64));
+    std::fs::write(pending_path, b"pending").unwrap();
+
+    let existing = BlobId::new();
+    journal(&db, "commit", file.hash.as_str(), Some(&existing)).await;
+
+    journal(&db, "delete", &"3".repeat(64), None).await;
+    let deleted_path = client_server.infra.object_path("files", &"3".repeat(64));
+    std::fs::write(deleted_path, b"deleted").unwrap();
+
+    let client_server = client_server.restart().await;
+    let infra = &client_server.infra;
+
+    assert_eq!(infra.files().await, vec![file.hash.clone()]);
+    assert_eq!(infra.blobs().await, vec![pending.to_string()]);
+
+    let data = client_server
+        .client
+        .download(file.hash, 0, 5)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(data, b"hello");
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM journal")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+}
+
+async fn journal(db: &SqlitePool, op: &str, hash: &str, blob: Option<&BlobId>) {
+    sqlx::query(
+        "INSERT INTO journal (op, hash, blob, created) VALUES ($1, $2, $3, datetime('now'))",
+    )
+    .bind(op)
+    .bind(hash)
+    .bind(blob.map(|b| b.to_string()))
+    .execute(db)
+    .await
+    .unwrap();
+}
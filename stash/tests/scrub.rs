@@ -0,0 +1,107 @@
+use stash::{Client, ContentHash, Failure, ScrubReport};
+use util::{ClientServer, TestInfra, create_file};
+
+mod util;
+
+#[tokio::test]
+async fn scrub() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = &client_server.client;
+
+    let file1 = create_file(client, "f1", &["test"], false, b"hello").await;
+    let file2 = create_file(client, "f2", &["test"], false, b"world").await;
+    let file3 = create_file(client, "f3", &["test"], false, b"again").await;
+
+    let infra = &client_server.infra;
+    std::fs::write(infra.object_path("files", file1.hash.as_str()), b"jello").unwrap();
+    std::fs::remove_file(infra.object_path("files", file2.hash.as_str())).unwrap();
+    std::fs::write(infra.object_path("files", &"0".repeat(64)), b"orphan").unwrap();
+
+    let report = scrub_all(client, false).await;
+    assert_eq!(report.checked, 3);
+    assert_eq!(report.corrupted, vec![file1.hash.clone()]);
+    assert_eq!(report.missing, vec![file2.hash.clone()]);
+    assert_eq!(report.orphaned, vec!["0".repeat(64)]);
+
+    let report = scrub_all(client, true).await;
+    assert_eq!(report.corrupted, vec![file1.hash.clone()]);
+
+    let quarantine = infra.objects("quarantine").await;
+    assert_eq!(quarantine, vec!["0".repeat(64), file1.hash.to_string()]);
+    assert_eq!(infra.files().await, vec![file3.hash.clone()]);
+
+    let report = scrub_all(client, false).await;
+    assert!(report.corrupted.is_empty());
+    assert!(report.orphaned.is_empty());
+    assert_eq!(report.missing.len(), 2);
+
+    let rsp = client.scrub(None, 0, false).await.unwrap();
+    assert!(matches!(rsp.err(), Failure::InvalidArgument(_)));
+}
+
+#[tokio::test]
+async fn scrub_chunks() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::configure(infra, |s| s.with_chunking(true)).await;
+    let client = &client_server.client;
+
+    // Small content is a single chunk with the same hash
+    let file1 = create_file(client, "f1", &["test"], false, b"hello").await;
+    let file2 = create_file(client, "f2", &["test"], false, b"world").await;
+
+    let infra = &client_server.infra;
+    std::fs::write(infra.object_path("chunks", file1.hash.as_str()), b"jello").unwrap();
+    std::fs::write(infra.object_path("chunks", &"5".repeat(64)), b"orphan").unwrap();
+
+    let report = scrub_all(client, false).await;
+    assert_eq!(report.checked, 2);
+    assert_eq!(report.corrupted, vec![file1.hash.clone()]);
+    assert_eq!(report.corrupted_chunks, vec![file1.hash.clone()]);
+    assert_eq!(report.orphaned_chunks, vec!["5".repeat(64)]);
+    assert!(report.orphaned.is_empty());
+
+    scrub_all(client, true).await;
+
+    let quarantine = infra.objects("quarantine").await;
+    assert_eq!(quarantine, vec![file1.hash.to_string(), "5".repeat(64)]);
+    assert_eq!(infra.objects("chunks").await, vec![file2.hash.to_string()]);
+
+    let report = scrub_all(client, false).await;
+    assert_eq!(report.missing, vec![file1.hash]);
+    assert!(report.corrupted_chunks.is_empty());
+    assert!(report.orphaned_chunks.is_empty());
+}
+
+async fn scrub_all(client: &Client, quarantine: bool) -> ScrubReport {
+    let mut total = ScrubReport {
+        checked: 0,
+        corrupted: vec![],
+        missing: vec![],
+        orphaned: vec![],
+        corrupted_chunks: vec![],
+        orphaned_chunks: vec![],
+        next: None,
+    };
+
+    let mut after: Option<ContentHash> = None;
+    loop {
+        let report = client.scrub(after, 1, quarantine).await.unwrap().unwrap();
+        assert!(report.checked <= 1);
+
+        total.checked += report.checked;
+        total.corrupted.extend(report.corrupted);
+        total.missing.extend(report.missing);
+        total.orphaned.extend(report.orphaned);
+        total.corrupted_chunks.extend(report.corrupted_chunks);
+        total.orphaned_chunks.extend(report.orphaned_chunks);
+
+        match report.next {
+            Some(next) => after = Some(next),
+            None => break,
+        }
+    }
+
+    total.missing.sort();
+    total
+}
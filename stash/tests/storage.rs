@@ -0,0 +1,860 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use stash::{
+    BlobId, EncryptedStorage, Error, Failure, LocalStorage, MasterKey, S3Config, S3Storage,
+    Storage, Tag,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use util::{ClientServer, TestInfra};
+
+mod util;
+
+const BUCKET: &str = "stash";
+const LIST_PAGE_SIZE: usize = 2;
+// Stands in for the 5 GB cap on single S3 PUTs and copies
+const PUT_LIMIT: usize = 64 * 1024;
+
+#[tokio::test]
+async fn local_storage() {
+    let infra = TestInfra::new().await;
+    exercise(&LocalStorage::open(infra.root.clone()).await.unwrap()).await;
+}
+
+#[tokio::test]
+async fn local_layout() {
+    let infra = TestInfra::new().await;
+    let hash = "ab".repeat(32);
+    let blob = BlobId::new().to_string();
+
+    std::fs::create_dir_all(infra.root.join("files")).unwrap();
+    std::fs::create_dir_all(infra.root.join("blobs")).unwrap();
+    std::fs::write(infra.root.join("files").join(&hash), b"hello").unwrap();
+    std::fs::write(infra.root.join("blobs").join(&blob), b"blob").unwrap();
+    std::fs::create_dir_all(infra.root.join("backup")).unwrap();
+    std::fs::write(infra.root.join("backup/notes.txt"), b"mine").unwrap();
+
+    let storage = LocalStorage::open(infra.root.clone()).await.unwrap();
+    assert!(infra.root.join("backup/notes.txt").exists());
+    assert_eq!(
+        std::fs::read_to_string(infra.root.join("layout")).unwrap(),
+        "fanout-2x2\n"
+    );
+
+    assert!(!infra.root.join("files").join(&hash).exists());
+    assert_eq!(
+        std::fs::read(infra.root.join("files/ab/ab").join(&hash)).unwrap(),
+        b"hello"
+    );
+    let blob_path = infra
+        .root
+        .join("blobs")
+        .join(&blob[0..2])
+        .join(&blob[2..4])
+        .join(&blob);
+    assert_eq!(std::fs::read(blob_path).unwrap(), b"blob");
+
+    assert_eq!(storage.list("files").await.unwrap(), vec![hash.clone()]);
+    assert_eq!(
+        read(&storage, &format!("files/{hash}"), 0, 5).await,
+        b"hello"
+    );
+    assert_eq!(
+        read(&storage, &format!("blobs/{blob}"), 0, 4).await,
+        b"blob"
+    );
+
+    let storage = LocalStorage::open(infra.root.clone()).await.unwrap();
+    assert_eq!(storage.list("blobs").await.unwrap(), vec![blob]);
+
+    std::fs::write(infra.root.join("layout"), "fanout-3x3\n").unwrap();
+    let rsp = LocalStorage::open(infra.root.clone()).await;
+    assert!(matches!(rsp, Err(Error::StorageError(_))));
+}
+
+#[tokio::test]
+async fn encrypted_storage() {
+    let infra = TestInfra::new().await;
+    let local = LocalStorage::open(infra.root.clone()).await.unwrap();
+    assert_eq!(local.rotate_keys().await.unwrap(), None);
+
+    let old_key = MasterKey::new([1; 32]);
+    let storage = EncryptedStorage::open(local.clone(), old_key.clone(), vec![])
+        .await
+        .unwrap();
+    exercise(&storage).await;
+    assert!(infra.objects("keys").await.is_empty());
+
+    let key = "files/content";
+    let mut content: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    storage
+        .put(key, &mut content.as_slice(), content.len() as u64)
+        .await
+        .unwrap();
+    assert_eq!(infra.objects("keys").await.len(), 1);
+    assert_eq!(
+        storage.stat(key).await.unwrap().unwrap().size,
+        content.len() as u64
+    );
+
+    let raw = std::fs::read(infra.object_path("files", "content")).unwrap();
+    assert!(raw.len() > content.len());
+    assert!(!raw.windows(32).any(|w| w == &content[1_000..1_032]));
+
+    assert_eq!(read(&storage, key, 0, 300_000).await, content);
+    assert_eq!(
+        read(&storage, key, 65_530, 20).await,
+        content[65_530..65_550]
+    );
+    assert_eq!(
+        read(&storage, key, 200_000, 100_000).await,
+        content[200_000..]
+    );
+
+    storage.write_at(key, 131_000, &[7; 2_000]).await.unwrap();
+    content[131_000..133_000].fill(7);
+    storage.append(key, &[9; 70_000]).await.unwrap();
+    content.extend([9; 70_000]);
+    assert_eq!(read(&storage, key, 0, content.len() as u64).await, content);
+
+    let sealed = std::fs::read(infra.object_path("files", "content")).unwrap();
+    let new_key = MasterKey::new([2; 32]);
+    let rotating = EncryptedStorage::open(local.clone(), new_key.clone(), vec![old_key.clone()])
+        .await
+        .unwrap();
+    assert_eq!(rotating.rotate_keys().await.unwrap(), Some(1));
+    assert_eq!(rotating.rotate_keys().await.unwrap(), Some(0));
+    assert_eq!(
+        std::fs::read(infra.object_path("files", "content")).unwrap(),
+        sealed
+    );
+
+    let storage = EncryptedStorage::open(local.clone(), new_key, vec![])
+        .await
+        .unwrap();
+    assert_eq!(read(&storage, key, 0, content.len() as u64).await, content);
+
+    let stale = EncryptedStorage::open(local.clone(), old_key, vec![])
+        .await
+        .unwrap();
+    let rsp = stale.get_range(key, 0, 1).await.err();
+    assert!(matches!(rsp, Some(Error::StorageError(_))));
+
+    let path = infra.object_path("files", "content");
+    let mut raw = std::fs::read(&path).unwrap();
+    raw[100_000] ^= 1;
+    std::fs::write(&path, raw).unwrap();
+
+    assert_eq!(read(&storage, key, 0, 1_000).await, content[..1_000]);
+    let mut data = vec![];
+    let rsp = storage
+        .get_range(key, 0, content.len() as u64)
+        .await
+        .unwrap()
+        .read_to_end(&mut data)
+        .await;
+    assert_eq!(rsp.unwrap_err().kind(), ErrorKind::InvalidData);
+
+    storage.delete(key).await.unwrap();
+    assert!(infra.objects("keys").await.is_empty());
+
+    let key = "files/truncated";
+    storage
+        .put(key, &mut [5; 100_000].as_slice(), 100_000)
+        .await
+        .unwrap();
+    let path = infra.object_path("files", "truncated");
+    let mut raw = std::fs::read(&path).unwrap();
+    raw.truncate(24 + 65_536 + 40);
+    std::fs::write(&path, raw).unwrap();
+
+    let mut data = vec![];
+    let rsp = storage
+        .get_range(key, 0, 65_536)
+        .await
+        .unwrap()
+        .read_to_end(&mut data)
+        .await;
+    assert_eq!(rsp.unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn encrypted_rotation() {
+    let infra = TestInfra::new().await;
+    let local = LocalStorage::open(infra.root.clone()).await.unwrap();
+    let old_key = MasterKey::new([1; 32]);
+    let storage = EncryptedStorage::open(local.clone(), old_key.clone(), vec![])
+        .await
+        .unwrap();
+
+    let keys: Vec<_> = (0..50).map(|i| format!("files/{i}")).collect();
+    for key in keys.iter() {
+        storage.put(key, &mut b"x".as_slice(), 1).await.unwrap();
+    }
+
+    // Objects deleted during a rotation take their data keys with them
+    let rotating = EncryptedStorage::open(local, MasterKey::new([2; 32]), vec![old_key])
+        .await
+        .unwrap();
+    let (rotated, _) = tokio::join!(rotating.rotate_keys(), async {
+        for key in keys.iter().skip(1) {
+            rotating.delete(key).await.unwrap();
+        }
+    });
+    assert!(rotated.unwrap().unwrap() <= 50);
+    assert_eq!(infra.objects("keys").await.len(), 1);
+    assert_eq!(rotating.rotate_keys().await.unwrap(), Some(0));
+    assert_eq!(read(&rotating, "files/0", 0, 1).await, b"x");
+}
+
+#[tokio::test]
+async fn encrypted_plaintext_storage() {
+    let infra = TestInfra::new().await;
+    let local = LocalStorage::open(infra.root.clone()).await.unwrap();
+    local
+        .put("files/plain", &mut b"plain".as_slice(), 5)
+        .await
+        .unwrap();
+
+    let rsp = EncryptedStorage::open(local.clone(), MasterKey::new([1; 32]), vec![]).await;
+    assert!(matches!(rsp, Err(Error::StorageError(_))));
+
+    local.delete("files/plain").await.unwrap();
+    assert!(!EncryptedStorage::is_encrypted(&local).await.unwrap());
+    EncryptedStorage::open(local.clone(), MasterKey::new([1; 32]), vec![])
+        .await
+        .unwrap();
+    assert_eq!(infra.objects("meta").await, vec!["encryption".to_string()]);
+    assert!(EncryptedStorage::is_encrypted(&local).await.unwrap());
+}
+
+#[tokio::test]
+async fn encrypted_server() {
+    let infra = TestInfra::new().await;
+    let local = LocalStorage::open(infra.root.clone()).await.unwrap();
+    let storage = EncryptedStorage::open(local, MasterKey::new([3; 32]), vec![])
+        .await
+        .unwrap();
+    let client_server = ClientServer::with_storage(infra, storage).await;
+    let client = &client_server.client;
+
+    let content = b"secret".repeat(50_000);
+    let file = client
+        .upload(
+            "f1".to_string(),
+            vec![Tag::from_str("test").unwrap()],
+            false,
+            content.len() as u64,
+            None,
+            content.as_slice(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+    let data = client
+        .download(file.hash.clone(), 100_000, 1_000)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(data, content[100_000..101_000].to_vec());
+
+    let rsp = client.rotate_keys().await.unwrap();
+    assert!(matches!(rsp.err(), Failure::Unauthorized(_)));
+
+    let admin = client_server.admin().await;
+    let report = admin.rotate_keys().await.unwrap().unwrap();
+    assert_eq!(report.rewrapped, 0);
+
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let admin = client_server.admin().await;
+    let rsp = admin.rotate_keys().await.unwrap();
+    assert!(matches!(rsp.err(), Failure::InvalidArgument(_)));
+}
+
+#[tokio::test]
+async fn s3_storage() {
+    let s3 = FakeS3::start().await;
+    let infra = TestInfra::new().await;
+    exercise(&s3.storage("", &infra).await).await;
+
+    let storage = s3.storage("nested/prefix", &infra).await;
+    exercise(&storage).await;
+
+    let keys = s3.keys();
+    assert!(keys.iter().all(|k| k.starts_with("nested/prefix/")));
+
+    storage
+        .put("blobs/a", &mut b"hello".as_slice(), 5)
+        .await
+        .unwrap();
+    storage.append("blobs/a", b" world").await.unwrap();
+    assert!(s3.keys().is_empty());
+    assert_eq!(infra.blobs().await, vec!["a".to_string()]);
+
+    storage.rename("blobs/a", "files/a").await.unwrap();
+    assert_eq!(s3.keys(), vec!["nested/prefix/files/a".to_string()]);
+    assert!(infra.blobs().await.is_empty());
+    assert_eq!(read(&storage, "files/a", 0, 11).await, b"hello world");
+
+    let err = storage.append("files/a", b"!").await.err();
+    assert!(matches!(err, Some(Error::StorageError(_))));
+
+    storage.rename("files/a", "blobs/a").await.unwrap();
+    assert!(s3.keys().is_empty());
+    assert_eq!(read(&storage, "blobs/a", 0, 11).await, b"hello world");
+}
+
+#[tokio::test]
+async fn s3_multipart() {
+    let s3 = FakeS3::start().await;
+    let infra = TestInfra::new().await;
+    let storage = s3.storage("", &infra).await;
+
+    for (size, multipart) in [
+        (PUT_LIMIT, false),
+        (PUT_LIMIT + 1, true),
+        (3 * PUT_LIMIT, true),
+    ] {
+        let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        let completed = s3.completed();
+
+        storage
+            .put("files/a", &mut data.as_slice(), size as u64)
+            .await
+            .unwrap();
+        assert_eq!(s3.completed() - completed, multipart as usize);
+
+        storage.rename("files/a", "files/b").await.unwrap();
+        assert_eq!(s3.completed() - completed, 2 * multipart as usize);
+        assert!(storage.stat("files/a").await.unwrap().is_none());
+        assert_eq!(read(&storage, "files/b", 0, size as u64).await, data);
+
+        storage.delete("files/b").await.unwrap();
+    }
+
+    let data = vec![1; 2 * PUT_LIMIT];
+    let err = storage
+        .put("files/c", &mut data.as_slice(), 3 * PUT_LIMIT as u64)
+        .await
+        .err();
+    assert!(matches!(err, Some(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof));
+    assert!(s3.keys().is_empty());
+    assert!(s3.bucket.lock().unwrap().uploads.is_empty());
+}
+
+#[tokio::test]
+async fn s3_endpoint_validation() {
+    let s3 = FakeS3::start().await;
+    let infra = TestInfra::new().await;
+    let staging = LocalStorage::open(infra.root.clone()).await.unwrap();
+
+    for endpoint in ["https://s3.amazonaws.com", "http://localhost:9000/"] {
+        let config = S3Config {
+            endpoint: endpoint.to_string(),
+            ..s3.config("")
+        };
+        assert!(S3Storage::new(config, staging.clone()).is_ok());
+    }
+
+    for endpoint in [
+        "ftp://s3.amazonaws.com",
+        "localhost:9000",
+        "http://localhost/bucket",
+    ] {
+        let config = S3Config {
+            endpoint: endpoint.to_string(),
+            ..s3.config("")
+        };
+        assert!(matches!(
+            S3Storage::new(config, staging.clone()),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn s3_server() {
+    let s3 = FakeS3::start().await;
+    let infra = TestInfra::new().await;
+    let storage = s3.storage("stash", &infra).await;
+    let client_server = ClientServer::with_storage(infra, storage).await;
+    let client = &client_server.client;
+
+    let content = b"hello world".repeat(10_000);
+    let file = client
+        .upload(
+            "f1".to_string(),
+            vec![Tag::from_str("test").unwrap()],
+            false,
+            content.len() as u64,
+            None,
+            content.as_slice(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(s3.keys(), vec![format!("stash/files/{}", file.hash)]);
+    assert!(client_server.infra.files().await.is_empty());
+
+    let data = client
+        .download(file.hash.clone(), 5, 20)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(data, content[5..25].to_vec());
+
+    let mut stream = client
+        .stream(file.hash.clone(), 0, file.size)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut data = vec![];
+    stream.read_to_end(&mut data).await.unwrap();
+    assert_eq!(data, content);
+
+    let blob = client.create_blob().await.unwrap().unwrap();
+    client
+        .append_blob(blob.name.clone(), 0, b"hello".to_vec())
+        .await
+        .unwrap()
+        .unwrap();
+    let blob = client
+        .append_blob(blob.name.clone(), 5, b" world".to_vec())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(blob.size, 11);
+    assert!(!s3.keys().contains(&format!("stash/blobs/{}", blob.name)));
+    assert_eq!(
+        client_server.infra.blobs().await,
+        vec![blob.name.to_string()]
+    );
+
+    let blob_file = client
+        .commit_blob(
+            blob.name.clone(),
+            "f2".to_string(),
+            vec![Tag::from_str("test").unwrap()],
+            false,
+            None,
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(client_server.infra.blobs().await.is_empty());
+
+    let data = client
+        .download(blob_file.hash.clone(), 0, 11)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(data, b"hello world".to_vec());
+
+    client.delete("f1".to_string()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
+    client.delete("f2".to_string()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
+    assert!(s3.keys().is_empty());
+}
+
+async fn exercise(storage: &impl Storage) {
+    assert!(storage.stat("files/a").await.unwrap().is_none());
+    assert!(storage.list("files").await.unwrap().is_empty());
+    assert_not_found(storage.get_range("files/a", 0, 1).await.err());
+    assert_not_found(storage.append("files/a", b"x").await.err());
+    storage.delete("files/a").await.unwrap();
+
+    storage
+        .put("files/a", &mut b"hello world!".as_slice(), 11)
+        .await
+        .unwrap();
+    assert_eq!(storage.stat("files/a").await.unwrap().unwrap().size, 11);
+    assert_eq!(read(storage, "files/a", 0, 11).await, b"hello world");
+    assert_eq!(read(storage, "files/a", 6, 5).await, b"world");
+    assert_eq!(read(storage, "files/a", 3, 0).await, b"");
+
+    let err = storage
+        .put("files/b", &mut b"short".as_slice(), 10)
+        .await
+        .err();
+    assert!(matches!(err, Some(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof));
+    assert!(storage.stat("files/b").await.unwrap().is_none());
+
+    storage
+        .put("blobs/b", &mut tokio::io::empty(), 0)
+        .await
+        .unwrap();
+    storage.append("blobs/b", b"hello").await.unwrap();
+    storage.append("blobs/b", b" there").await.unwrap();
+    storage.write_at("blobs/b", 6, b"world").await.unwrap();
+    storage.write_at("blobs/b", 13, b"!").await.unwrap();
+    assert_eq!(read(storage, "blobs/b", 0, 14).await, b"hello world\0\0!");
+
+    storage.rename("blobs/b", "files/b").await.unwrap();
+    assert!(storage.stat("blobs/b").await.unwrap().is_none());
+    assert_eq!(storage.stat("files/b").await.unwrap().unwrap().size, 14);
+
+    for name in ["c", "d", "e"] {
+        let key = format!("files/{name}");
+        storage.put(&key, &mut b"x".as_slice(), 1).await.unwrap();
+    }
+
+    let mut names = storage.list("files").await.unwrap();
+    names.sort();
+    assert_eq!(names, ["a", "b", "c", "d", "e"]);
+    assert!(storage.list("blobs").await.unwrap().is_empty());
+
+    for name in names {
+        storage.delete(&format!("files/{name}")).await.unwrap();
+    }
+    assert!(storage.list("files").await.unwrap().is_empty());
+}
+
+async fn read(storage: &impl Storage, key: &str, start: u64, len: u64) -> Vec<u8> {
+    let mut data = vec![];
+    storage
+        .get_range(key, start, len)
+        .await
+        .unwrap()
+        .read_to_end(&mut data)
+        .await
+        .unwrap();
+    data
+}
+
+fn assert_not_found(err: Option<Error>) {
+    assert!(matches!(err, Some(Error::IoError(e)) if e.kind() == ErrorKind::NotFound));
+}
+
+struct FakeS3 {
+    endpoint: String,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Default)]
+struct Bucket {
+    objects: BTreeMap<String, Vec<u8>>,
+    uploads: BTreeMap<String, BTreeMap<u32, Vec<u8>>>,
+    next_upload: usize,
+    completed: usize,
+}
+
+impl FakeS3 {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let bucket = Arc::new(Mutex::new(Bucket::default()));
+
+        let state = bucket.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, state.clone()));
+            }
+        });
+
+        Self { endpoint, bucket }
+    }
+
+    fn config(&self, prefix: &str) -> S3Config {
+        S3Config {
+            endpoint: self.endpoint.clone(),
+            bucket: BUCKET.to_string(),
+            region: "us-east-1".to_string(),
+            access_key: "minio".to_string(),
+            secret_key: "minio123".to_string(),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    async fn storage(&self, prefix: &str, staging: &TestInfra) -> S3Storage {
+        let staging = LocalStorage::open(staging.root.clone()).await.unwrap();
+        S3Storage::new(self.config(prefix), staging)
+            .unwrap()
+            .with_part_size(PUT_LIMIT as u64)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.bucket
+            .lock()
+            .unwrap()
+            .objects
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    fn completed(&self) -> usize {
+        self.bucket.lock().unwrap().completed
+    }
+}
+
+async fn serve(stream: TcpStream, bucket: Arc<Mutex<Bucket>>) {
+    let mut stream = BufReader::new(stream);
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap().to_string();
+    let target = parts.next().unwrap().to_string();
+
+    let mut headers = BTreeMap::new();
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await.unwrap();
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => {
+                headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+            }
+            None => break,
+        }
+    }
+
+    assert!(headers["authorization"].starts_with("AWS4-HMAC-SHA256 Credential=minio/"));
+
+    let len = headers
+        .get("content-length")
+        .map_or(0, |v| v.parse().unwrap());
+    let mut body = vec![0; len];
+    if stream.read_exact(&mut body).await.is_err() {
+        return;
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query: BTreeMap<_, _> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (decode(k), decode(v)))
+        .collect();
+    let path = decode(path);
+    let key = path
+        .strip_prefix(&format!("/{BUCKET}"))
+        .unwrap()
+        .trim_start_matches('/')
+        .to_string();
+
+    let (status, extra, body) = {
+        let bucket = &mut *bucket.lock().unwrap();
+        let objects = &mut bucket.objects;
+
+        let source = headers.get("x-amz-copy-source").map(|source| {
+            let source = decode(source);
+            let data = objects.get(source.strip_prefix(&format!("/{BUCKET}/")).unwrap());
+            match (data, headers.get("x-amz-copy-source-range")) {
+                (Some(data), Some(range)) => {
+                    let (start, end) = parse_range(range, data.len());
+                    Some(data[start..end].to_vec())
+                }
+                (data, _) => data.cloned(),
+            }
+        });
+
+        match (method.as_str(), query.get("uploadId")) {
+            ("POST", None) => {
+                assert!(query.contains_key("uploads"));
+                bucket.next_upload += 1;
+                let upload_id = format!("upload-{}", bucket.next_upload);
+                bucket.uploads.insert(upload_id.clone(), BTreeMap::new());
+                let xml = format!("<Result><UploadId>{upload_id}</UploadId></Result>");
+                (200, vec![], xml.into_bytes())
+            }
+            ("POST", Some(upload_id)) => match bucket.uploads.remove(upload_id) {
+                Some(mut parts) => {
+                    let body = String::from_utf8(body).unwrap();
+                    let numbers = xml_values(&body, "PartNumber");
+                    let etags = xml_values(&body, "ETag");
+                    assert_eq!(numbers.len(), parts.len());
+
+                    let mut data = vec![];
+                    for (number, etag) in numbers.iter().zip(etags) {
+                        let part = parts.remove(&number.parse().unwrap()).unwrap();
+                        assert_eq!(etag, part_etag(&part));
+                        data.extend(part);
+                    }
+
+                    objects.insert(key, data);
+                    bucket.completed += 1;
+                    (200, vec![], b"<CompleteMultipartUploadResult/>".to_vec())
+                }
+                None => (404, vec![], vec![]),
+            },
+            ("PUT", Some(_)) if source == Some(None) => (404, vec![], vec![]),
+            ("PUT", Some(upload_id)) => {
+                let number = query["partNumber"].parse().unwrap();
+                let copied = source.is_some();
+                let part = source.flatten().unwrap_or(body);
+                assert!(part.len() <= PUT_LIMIT);
+
+                let etag = part_etag(&part);
+                bucket
+                    .uploads
+                    .get_mut(upload_id)
+                    .unwrap()
+                    .insert(number, part);
+                match copied {
+                    true => {
+                        let etag = etag.replace('"', "&quot;");
+                        let xml = format!("<CopyPartResult><ETag>{etag}</ETag></CopyPartResult>");
+                        (200, vec![], xml.into_bytes())
+                    }
+                    false => (200, vec![("etag", etag)], vec![]),
+                }
+            }
+            ("DELETE", Some(upload_id)) => {
+                bucket.uploads.remove(upload_id);
+                (204, vec![], vec![])
+            }
+            ("PUT", None) => match source {
+                Some(Some(data)) if data.len() > PUT_LIMIT => {
+                    (400, vec![], b"<Error>EntityTooLarge</Error>".to_vec())
+                }
+                Some(Some(data)) => {
+                    objects.insert(key, data);
+                    (
+                        200,
+                        vec![],
+                        b"<CopyObjectResult></CopyObjectResult>".to_vec(),
+                    )
+                }
+                Some(None) => (404, vec![], vec![]),
+                None if body.len() > PUT_LIMIT => {
+                    (400, vec![], b"<Error>EntityTooLarge</Error>".to_vec())
+                }
+                None => {
+                    objects.insert(key, body);
+                    (200, vec![], vec![])
+                }
+            },
+            ("DELETE", None) => {
+                objects.remove(&key);
+                (204, vec![], vec![])
+            }
+            ("HEAD", _) => match objects.get(&key) {
+                Some(data) => (
+                    200,
+                    vec![("last-modified", Utc::now().to_rfc2822())],
+                    data.clone(),
+                ),
+                None => (404, vec![], vec![]),
+            },
+            ("GET", _) if key.is_empty() => (200, vec![], list(objects, &query).into_bytes()),
+            ("GET", _) => match (objects.get(&key), headers.get("range")) {
+                (None, _) => (404, vec![], vec![]),
+                (Some(data), Some(range)) => {
+                    let (start, end) = parse_range(range, data.len());
+                    (206, vec![], data[start..end].to_vec())
+                }
+                (Some(data), None) => (200, vec![], data.clone()),
+            },
+            _ => (405, vec![], vec![]),
+        }
+    };
+
+    // Reads are sent chunked, as S3 may do, to check that they are decoded.
+    let chunked = method == "GET";
+    let mut rsp = format!("HTTP/1.1 {status} Fake\r\nconnection: close\r\n");
+    match chunked {
+        true => rsp.push_str("transfer-encoding: chunked\r\n"),
+        false => rsp.push_str(&format!("content-length: {}\r\n", body.len())),
+    }
+    for (name, value) in extra {
+        rsp.push_str(&format!("{name}: {value}\r\n"));
+    }
+    rsp.push_str("\r\n");
+
+    let stream = stream.get_mut();
+    stream.write_all(rsp.as_bytes()).await.unwrap();
+    if chunked {
+        for chunk in body.chunks(4096) {
+            let chunk = [format!("{:x}\r\n", chunk.len()).as_bytes(), chunk, b"\r\n"].concat();
+            stream.write_all(&chunk).await.unwrap();
+        }
+        stream.write_all(b"0\r\n\r\n").await.unwrap();
+    } else if method != "HEAD" {
+        stream.write_all(&body).await.unwrap();
+    }
+    stream.shutdown().await.ok();
+}
+
+fn parse_range(range: &str, len: usize) -> (usize, usize) {
+    let (start, end) = range
+        .strip_prefix("bytes=")
+        .and_then(|r| r.split_once('-'))
+        .unwrap();
+    (
+        start.parse().unwrap(),
+        (end.parse::<usize>().unwrap() + 1).min(len),
+    )
+}
+
+fn part_etag(part: &[u8]) -> String {
+    format!(
+        "\"{}-{}\"",
+        part.len(),
+        part.iter().map(|&b| b as usize).sum::<usize>()
+    )
+}
+
+fn xml_values(text: &str, tag: &str) -> Vec<String> {
+    text.split(&format!("<{tag}>"))
+        .skip(1)
+        .filter_map(|rest| rest.split_once(&format!("</{tag}>")))
+        .map(|(value, _)| value.replace("&quot;", "\""))
+        .collect()
+}
+
+fn list(objects: &BTreeMap<String, Vec<u8>>, query: &BTreeMap<String, String>) -> String {
+    assert_eq!(query["list-type"], "2");
+
+    let prefix = &query["prefix"];
+    let after = query.get("continuation-token").cloned().unwrap_or_default();
+    let keys: Vec<_> = objects
+        .keys()
+        .filter(|k| k.starts_with(prefix.as_str()) && **k > after)
+        .collect();
+
+    let page = &keys[..keys.len().min(LIST_PAGE_SIZE)];
+    let mut xml = "<ListBucketResult>".to_string();
+    for key in page {
+        xml.push_str(&format!("<Contents><Key>{key}</Key></Contents>"));
+    }
+
+    if keys.len() > page.len() {
+        xml.push_str(&format!(
+            "<IsTruncated>true</IsTruncated><NextContinuationToken>{}</NextContinuationToken>",
+            page.last().unwrap()
+        ));
+    } else {
+        xml.push_str("<IsTruncated>false</IsTruncated>");
+    }
+
+    xml.push_str("</ListBucketResult>");
+    xml
+}
+
+fn decode(s: &str) -> String {
+    let mut out = vec![];
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex: String = bytes.by_ref().take(2).map(char::from).collect();
+                out.push(u8::from_str_radix(&hex, 16).unwrap());
+            }
+            b => out.push(b),
+        }
+    }
+
+    String::from_utf8(out).unwrap()
+}
@@ -1,7 +1,7 @@
 use std::str::FromStr;
 
-use stash::{Response, Tag};
-use util::{ClientServer, TestInfra};
+use stash::{Client, Failure, FileSelection, Response, Tag, TagInfo};
+use util::{ClientServer, TestInfra, create_file};
 
 mod util;
 
@@ -17,7 +17,7 @@ async fn tags() {
 
     let blob = client.create_blob().await.unwrap().unwrap();
     let blob = client
-        .append_blob(blob.name, b"hello".to_vec())
+        .append_blob(blob.name, 0, b"hello".to_vec())
         .await
         .unwrap()
         .unwrap();
@@ -28,6 +28,8 @@ async fn tags() {
             "f".to_string(),
             vec![Tag::from_str("t1").unwrap()],
             false,
+            None,
+            None,
         )
         .await
         .unwrap()
@@ -37,3 +39,269 @@ async fn tags() {
     assert!(matches!(rsp, Response::Ok(_)));
     assert_eq!(rsp.unwrap(), vec!["t1".to_string()]);
 }
+
+#[tokio::test]
+async fn tag_editing() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = client_server.client;
+
+    create_file(&client, "report-1", &["draft"], false, b"hello").await;
+    create_file(&client, "report-2", &["draft", "q3"], false, b"hello").await;
+    create_file(&client, "notes", &["draft"], false, b"hello").await;
+
+    let files = client
+        .edit_tags(
+            FileSelection::Names(vec!["notes".to_string()]),
+            parse_tags(&["misc", "draft"]),
+            vec![],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].tags, vec!["draft".to_string(), "misc".to_string()]);
+
+    let files = client
+        .edit_tags(
+            FileSelection::Search {
+                tag: "draft".to_string(),
+                term: "report".to_string(),
+            },
+            parse_tags(&["final"]),
+            parse_tags(&["draft"]),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    let edited: Vec<_> = files
+        .iter()
+        .map(|f| (f.name.as_str(), f.tags.clone()))
+        .collect();
+    assert_eq!(
+        edited,
+        vec![
+            ("report-1", vec!["final".to_string()]),
+            ("report-2", vec!["final".to_string(), "q3".to_string()]),
+        ]
+    );
+
+    let desc = client
+        .describe("report-2".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(desc.tags, vec!["final".to_string(), "q3".to_string()]);
+
+    let final_tag = Tag::from_str("final").unwrap();
+    let files = client.list(final_tag, None).await.unwrap().unwrap();
+    assert_eq!(files.len(), 2);
+
+    let rsp = client
+        .edit_tags(
+            FileSelection::Names(vec!["report-2".to_string(), "report-1".to_string()]),
+            vec![],
+            parse_tags(&["final"]),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::InvalidArgument("File report-1 must keep at least one tag".to_string())
+    );
+
+    let desc = client
+        .describe("report-2".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(desc.tags, vec!["final".to_string(), "q3".to_string()]);
+
+    let rsp = client
+        .edit_tags(
+            FileSelection::Names(vec!["missing".to_string()]),
+            parse_tags(&["x"]),
+            vec![],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::NotFound("No such file missing".to_string())
+    );
+
+    let rsp = client
+        .edit_tags(
+            FileSelection::Names(vec!["notes".to_string()]),
+            vec![],
+            vec![],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::InvalidArgument("No tags to add or remove".to_string())
+    );
+}
+
+#[tokio::test]
+async fn tag_management() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = &client_server.client;
+
+    create_file(client, "a", &["draft"], false, b"hello").await;
+    create_file(client, "b", &["draft", "q3"], false, b"hello").await;
+    create_file(client, "c", &["old"], false, b"hello").await;
+
+    assert_eq!(
+        tag_stats(client).await,
+        vec!["draft 2 10", "old 1 5", "q3 1 5"]
+    );
+
+    client
+        .rename_tag(tag("old"), tag("archive"))
+        .await
+        .unwrap()
+        .unwrap();
+    let desc = client.describe("c".to_string()).await.unwrap().unwrap();
+    assert_eq!(desc.tags, vec!["archive".to_string()]);
+
+    let rsp = client.rename_tag(tag("draft"), tag("q3")).await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("Tag already exists".to_string())
+    );
+
+    let rsp = client.rename_tag(tag("missing"), tag("x")).await.unwrap();
+    assert_eq!(rsp.err(), Failure::NotFound("No such tag".to_string()));
+
+    client
+        .merge_tags(tag("q3"), tag("draft"))
+        .await
+        .unwrap()
+        .unwrap();
+    let desc = client.describe("b".to_string()).await.unwrap().unwrap();
+    assert_eq!(desc.tags, vec!["draft".to_string()]);
+    assert_eq!(
+        client.tags().await.unwrap().unwrap(),
+        vec!["archive".to_string(), "draft".to_string()]
+    );
+
+    let rsp = client.merge_tags(tag("draft"), tag("draft")).await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::InvalidArgument("Cannot merge a tag into itself".to_string())
+    );
+
+    let rsp = client.delete_tag(tag("draft"), false).await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("2 files would be left without tags".to_string())
+    );
+
+    client
+        .edit_tags(
+            FileSelection::Names(vec!["a".to_string(), "b".to_string()]),
+            parse_tags(&["final"]),
+            vec![],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    client
+        .delete_tag(tag("draft"), false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tag_stats(client).await, vec!["archive 1 5", "final 2 10"]);
+
+    client
+        .delete_tag(tag("archive"), true)
+        .await
+        .unwrap()
+        .unwrap();
+    let desc = client.describe("c".to_string()).await.unwrap().unwrap();
+    assert!(desc.tags.is_empty());
+
+    create_file(client, "d", &["temp"], false, b"hello").await;
+    client
+        .edit_tags(
+            FileSelection::Names(vec!["d".to_string()]),
+            parse_tags(&["final"]),
+            parse_tags(&["temp"]),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        client.tags().await.unwrap().unwrap(),
+        vec!["final".to_string()]
+    );
+
+    create_file(client, "e", &["scratch"], false, b"hello").await;
+    client.delete("e".to_string()).await.unwrap().unwrap();
+    assert_eq!(tag_stats(client).await, vec!["final 3 15", "scratch 0 0"]);
+
+    client.purge_trash(None).await.unwrap().unwrap();
+    assert_eq!(tag_stats(client).await, vec!["final 3 15"]);
+}
+
+#[tokio::test]
+async fn tags_of_old_versions() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = &client_server.client;
+
+    create_file(client, "v", &["old"], false, b"hello").await;
+    create_file(client, "v", &["new"], true, b"world").await;
+    create_file(client, "t", &["gone"], false, b"again").await;
+    client.delete("t".to_string()).await.unwrap().unwrap();
+
+    for name in ["old", "gone"] {
+        let rsp = client.delete_tag(tag(name), false).await.unwrap();
+        assert_eq!(
+            rsp.err(),
+            Failure::Conflict("1 files would be left without tags".to_string())
+        );
+
+        client.delete_tag(tag(name), true).await.unwrap().unwrap();
+    }
+
+    let rsp = client.restore_version("v".to_string(), 1).await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("Version 1 has no tags left to restore".to_string())
+    );
+
+    let trash = client.trash().await.unwrap().unwrap();
+    let rsp = client.restore_trash(trash[0].id, None).await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("Trashed file has no tags left to restore".to_string())
+    );
+    assert!(matches!(
+        client.describe("t".to_string()).await.unwrap(),
+        Response::Err(_)
+    ));
+    assert_eq!(client.trash().await.unwrap().unwrap(), trash);
+}
+
+fn tag(name: &str) -> Tag {
+    Tag::from_str(name).unwrap()
+}
+
+async fn tag_stats(client: &Client) -> Vec<String> {
+    client
+        .tag_stats()
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|TagInfo { name, files, size }| format!("{name} {files} {size}"))
+        .collect()
+}
+
+fn parse_tags(names: &[&str]) -> Vec<Tag> {
+    names.iter().map(|t| Tag::from_str(t).unwrap()).collect()
+}
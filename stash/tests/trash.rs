@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use stash::Failure;
+use util::{ClientServer, TestInfra, create_file};
+
+mod util;
+
+#[tokio::test]
+async fn trash_restore() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = &client_server.client;
+
+    let file1 = create_file(client, "doc", &["test"], false, b"one").await;
+    let file2 = create_file(client, "doc", &["test"], true, b"two").await;
+
+    client.delete("doc".to_string()).await.unwrap().unwrap();
+
+    let rsp = client.describe("doc".to_string()).await.unwrap();
+    assert_eq!(rsp.err(), Failure::NotFound("No such file".to_string()));
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.files, 0);
+    assert_eq!(stats.contents, 2);
+
+    let entries = client.trash().await.unwrap().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "doc");
+    assert_eq!(entries[0].hash, file2.hash);
+    assert_eq!(entries[0].versions, 2);
+    assert_eq!(
+        entries[0].deleted_by,
+        client_server.client_sk.public().to_string()
+    );
+
+    let file3 = create_file(client, "doc", &["test"], false, b"three").await;
+
+    let rsp = client.restore_trash(entries[0].id, None).await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("File already exists".to_string())
+    );
+
+    let restored = client
+        .restore_trash(entries[0].id, Some("doc-old".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(restored.name, "doc-old");
+    assert_eq!(restored.hash, file2.hash);
+
+    let versions = client
+        .versions("doc-old".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    let hashes: Vec<_> = versions.iter().map(|v| v.hash.clone()).collect();
+    assert_eq!(hashes, vec![file2.hash.clone(), file1.hash.clone()]);
+
+    assert!(client.trash().await.unwrap().unwrap().is_empty());
+
+    let rsp = client.restore_trash(entries[0].id, None).await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::NotFound("No such trash entry".to_string())
+    );
+
+    client.delete("doc".to_string()).await.unwrap().unwrap();
+    let entries = client.trash().await.unwrap().unwrap();
+    assert_eq!(entries.len(), 1);
+
+    let purged = client.purge_trash(Some(entries[0].id)).await.unwrap();
+    assert_eq!(purged.unwrap(), 1);
+
+    let mut expected = vec![file1.hash, file2.hash];
+    expected.sort();
+    assert_eq!(client_server.infra.files().await, expected);
+    assert!(!client.has_content(file3.hash).await.unwrap().unwrap());
+
+    let rsp = client.purge_trash(Some(entries[0].id)).await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::NotFound("No such trash entry".to_string())
+    );
+}
+
+#[tokio::test]
+async fn trash_version_limit() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = &client_server.client;
+
+    create_file(client, "log", &["test"], false, b"v1").await;
+    client
+        .set_version_limit("log".to_string(), Some(1))
+        .await
+        .unwrap()
+        .unwrap();
+    client.delete("log".to_string()).await.unwrap().unwrap();
+
+    create_file(client, "log", &["test"], false, b"new1").await;
+    create_file(client, "log", &["test"], true, b"new2").await;
+    let versions = client.versions("log".to_string()).await.unwrap().unwrap();
+    assert_eq!(versions.len(), 2);
+
+    let entries = client.trash().await.unwrap().unwrap();
+    client
+        .restore_trash(entries[0].id, Some("log-old".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+
+    create_file(client, "log-old", &["test"], true, b"v2").await;
+    let versions = client
+        .versions("log-old".to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(versions.len(), 1);
+}
+
+#[tokio::test]
+async fn trash_expiry() {
+    let infra = TestInfra::new().await;
+    let mut server = None;
+    let client_server = ClientServer::configure(infra, |s| {
+        let s = s.with_trash_retention(Duration::from_secs(3600));
+        server = Some(s.clone());
+        s
+    })
+    .await;
+    let server = server.unwrap();
+    let client = &client_server.client;
+
+    create_file(client, "a", &["test"], false, b"a").await;
+    client.delete("a".to_string()).await.unwrap().unwrap();
+
+    assert_eq!(server.expire_trash().await.unwrap(), 0);
+    assert_eq!(client_server.infra.files().await.len(), 1);
+
+    let server = server.with_trash_retention(Duration::ZERO);
+    assert_eq!(server.expire_trash().await.unwrap(), 1);
+
+    assert!(client.trash().await.unwrap().unwrap().is_empty());
+    assert!(client_server.infra.files().await.is_empty());
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.contents, 0);
+}
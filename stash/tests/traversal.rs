@@ -0,0 +1,82 @@
+use std::str::FromStr;
+
+use stash::{BlobId, Cmd, ContentHash, Failure, Response};
+use util::{ClientServer, TestInfra};
+
+mod util;
+
+#[tokio::test]
+async fn blob_traversal() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+
+    let blob = BlobId::new();
+    let cmd = Cmd::DescribeBlob { name: blob.clone() };
+    let data = bincode::encode_to_vec(&cmd, bincode::config::standard()).unwrap();
+
+    let rsp = client_server.raw_request(&data).await.unwrap();
+    let rsp: Response<stash::Blob> = bincode::decode_from_slice(&rsp, bincode::config::standard())
+        .unwrap()
+        .0;
+    assert_eq!(rsp.err(), Failure::NotFound("No such blob".to_string()));
+
+    let evil = splice(
+        &data,
+        blob.to_string().as_bytes(),
+        b"././././././././././././../server.db",
+    );
+
+    let rsp = client_server.raw_request(&evil).await;
+    assert!(rsp.is_err());
+}
+
+#[tokio::test]
+async fn content_traversal() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+
+    let hash = ContentHash::from_str(&"0".repeat(64)).unwrap();
+    let cmd = Cmd::Download {
+        hash: hash.clone(),
+        start: 0,
+        len: 16,
+    };
+    let data = bincode::encode_to_vec(&cmd, bincode::config::standard()).unwrap();
+
+    let rsp = client_server.raw_request(&data).await.unwrap();
+    let rsp: Response<Vec<u8>> = bincode::decode_from_slice(&rsp, bincode::config::standard())
+        .unwrap()
+        .0;
+    assert_eq!(rsp.err(), Failure::NotFound("No such file".to_string()));
+
+    let traversal = format!("{}../server.db", "./".repeat(26));
+    let evil = splice(&data, hash.as_str().as_bytes(), traversal.as_bytes());
+
+    let rsp = client_server.raw_request(&evil).await;
+    assert!(rsp.is_err());
+}
+
+#[test]
+fn identifier_decoding() {
+    let config = bincode::config::standard();
+    let data = bincode::encode_to_vec("../../server.db".to_string(), config).unwrap();
+
+    let blob: Result<(BlobId, usize), _> = bincode::decode_from_slice(&data, config);
+    assert!(blob.is_err());
+
+    let hash: Result<(ContentHash, usize), _> = bincode::decode_from_slice(&data, config);
+    assert!(hash.is_err());
+}
+
+fn splice(data: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    assert_eq!(from.len(), to.len());
+
+    let at = data
+        .windows(from.len())
+        .position(|w| w == from)
+        .expect("identifier not found in encoded command");
+
+    let mut data = data.to_vec();
+    data[at..at + to.len()].copy_from_slice(to);
+    data
+}
@@ -1,7 +1,12 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, str::FromStr};
 
-use iroh::{Endpoint, NodeId, SecretKey, Watcher, protocol::Router};
-use stash::{Client, NodeAuth, Server};
+use iroh::{
+    Endpoint, NodeAddr, NodeId, SecretKey, Watcher, endpoint::SendStream, protocol::Router,
+};
+use stash::{
+    Client, ContentHash, File, Hello, Limits, NodeAuth, PROTOCOL_VERSION, Server, Storage, Tag,
+};
+use tokio::io::AsyncReadExt;
 use uuid::Uuid;
 
 pub struct TestInfra {
@@ -17,46 +22,67 @@ impl Drop for TestInfra {
 #[allow(dead_code)]
 impl TestInfra {
     pub async fn new() -> Self {
-        let root = PathBuf::from(format!("test-infra-{}", Uuid::new_v4().to_string()));
+        let root = PathBuf::from(format!("test-infra-{}", Uuid::new_v4()));
         std::fs::create_dir(&root).unwrap();
 
         TestInfra { root }
     }
 
     pub async fn blobs(&self) -> Vec<String> {
-        let mut blobs_dir = tokio::fs::read_dir(self.root.join("blobs")).await.unwrap();
-
-        let mut blobs = vec![];
-        while let Some(entry) = blobs_dir.next_entry().await.unwrap() {
-            if entry.file_type().await.unwrap().is_file() {
-                blobs.push(entry.file_name().into_string().unwrap());
-            }
-        }
+        self.objects("blobs").await
+    }
 
-        blobs
+    pub async fn files(&self) -> Vec<ContentHash> {
+        let files = self.objects("files").await;
+        files.into_iter().map(|f| f.parse().unwrap()).collect()
     }
 
-    pub async fn files(&self) -> Vec<String> {
-        let mut files_dir = tokio::fs::read_dir(self.root.join("files")).await.unwrap();
+    pub async fn objects(&self, dir: &str) -> Vec<String> {
+        let mut objects = vec![];
+        let mut dirs = vec![(self.root.join(dir), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+                continue;
+            };
 
-        let mut files = vec![];
-        while let Some(entry) = files_dir.next_entry().await.unwrap() {
-            if entry.file_type().await.unwrap().is_file() {
-                files.push(entry.file_name().into_string().unwrap());
+            while let Some(entry) = entries.next_entry().await.unwrap() {
+                let file_type = entry.file_type().await.unwrap();
+                if depth < 2 && file_type.is_dir() {
+                    dirs.push((entry.path(), depth + 1));
+                } else if depth == 2 && file_type.is_file() {
+                    objects.push(entry.file_name().into_string().unwrap());
+                }
             }
         }
 
-        files
+        objects.sort();
+        objects
+    }
+
+    pub fn object_path(&self, dir: &str, name: &str) -> PathBuf {
+        let path = self
+            .root
+            .join(dir)
+            .join(&name[0..2])
+            .join(&name[2..4])
+            .join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        path
     }
 }
 
-struct TestAuth {
+pub struct TestAuth {
     allow: NodeId,
+    admin: NodeId,
 }
 
 impl NodeAuth for TestAuth {
     async fn allow(&self, node: NodeId) -> bool {
-        node == self.allow
+        node == self.allow || node == self.admin
+    }
+
+    async fn admin(&self, node: NodeId) -> bool {
+        node == self.admin
     }
 }
 
@@ -65,15 +91,54 @@ pub struct ClientServer {
     pub infra: TestInfra,
     pub client: Client,
     pub client_sk: SecretKey,
+    pub admin_sk: SecretKey,
     pub server: Router,
     pub server_sk: SecretKey,
 }
 
+#[allow(dead_code)]
 impl ClientServer {
     pub async fn new(infra: TestInfra) -> Self {
+        Self::with_limits(infra, Limits::default()).await
+    }
+
+    pub async fn with_limits(infra: TestInfra, limits: Limits) -> Self {
+        Self::configure(infra, |server| server.with_limits(limits)).await
+    }
+
+    pub async fn configure(
+        infra: TestInfra,
+        f: impl FnOnce(Server<TestAuth>) -> Server<TestAuth>,
+    ) -> Self {
+        Self::start(infra, async |auth, root| {
+            f(Server::new(auth, root).await.unwrap())
+        })
+        .await
+    }
+
+    pub async fn with_storage<S: Storage>(infra: TestInfra, storage: S) -> Self {
+        Self::configure_storage(infra, storage, |server| server).await
+    }
+
+    pub async fn configure_storage<S: Storage>(
+        infra: TestInfra,
+        storage: S,
+        f: impl FnOnce(Server<TestAuth, S>) -> Server<TestAuth, S>,
+    ) -> Self {
+        Self::start(infra, async |auth, root| {
+            f(Server::with_storage(auth, root, storage).await.unwrap())
+        })
+        .await
+    }
+
+    async fn start<S: Storage>(
+        infra: TestInfra,
+        build: impl AsyncFnOnce(TestAuth, PathBuf) -> Server<TestAuth, S>,
+    ) -> Self {
         let mut rng = rand::thread_rng();
         let server_sk = SecretKey::generate(&mut rng);
         let client_sk = SecretKey::generate(&mut rng);
+        let admin_sk = SecretKey::generate(&mut rng);
 
         let server_endpoint = Endpoint::builder()
             .discovery_n0()
@@ -82,37 +147,122 @@ impl ClientServer {
             .await
             .unwrap();
 
+        let auth = TestAuth {
+            allow: client_sk.public(),
+            admin: admin_sk.public(),
+        };
+        let stash_server = build(auth, infra.root.clone()).await;
+
         let server = Router::builder(server_endpoint)
-            .accept(
-                stash::ALPN,
-                Server::new(
-                    TestAuth {
-                        allow: client_sk.public(),
-                    },
-                    infra.root.clone(),
-                )
-                .await
-                .unwrap(),
-            )
+            .accept(stash::ALPN, stash_server)
             .spawn();
 
         let server_addr = server.endpoint().node_addr().initialized().await;
 
-        let client_endpoint = Endpoint::builder()
-            .discovery_n0()
-            .secret_key(client_sk.clone())
-            .bind()
-            .await
-            .unwrap();
-
-        let client = stash::Client::with_addr(client_endpoint, server_addr);
+        let client = connect(&client_sk, server_addr).await;
 
         Self {
             infra,
             client,
             client_sk,
+            admin_sk,
             server,
             server_sk,
         }
     }
+
+    pub async fn admin(&self) -> Client {
+        let server_addr = self.server.endpoint().node_addr().initialized().await;
+        connect(&self.admin_sk, server_addr).await
+    }
+
+    pub async fn restart(self) -> Self {
+        self.client.close().await;
+        self.server.shutdown().await.unwrap();
+        Self::new(self.infra).await
+    }
+
+    pub async fn raw_request(&self, request: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let endpoint = Endpoint::builder()
+            .discovery_n0()
+            .secret_key(self.client_sk.clone())
+            .bind()
+            .await?;
+
+        let server_addr = self.server.endpoint().node_addr().initialized().await;
+        let conn = endpoint.connect(server_addr, stash::ALPN).await?;
+
+        let config = bincode::config::standard();
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+        };
+        let hello = bincode::encode_to_vec(&hello, config)?;
+        let (mut tx, mut rx) = conn.open_bi().await?;
+        write_frame(&mut tx, &hello).await?;
+        tx.finish()?;
+        rx.read_to_end(10_000).await?;
+
+        let (mut tx, mut rx) = conn.open_bi().await?;
+        write_frame(&mut tx, request).await?;
+        tx.finish()?;
+        let rsp = rx.read_to_end(10_000_000).await;
+
+        conn.close(0u32.into(), b"bye");
+        Ok(rsp?)
+    }
+}
+
+#[allow(dead_code)]
+pub async fn create_file(
+    client: &Client,
+    name: &str,
+    tags: &[&str],
+    replace: bool,
+    content: &[u8],
+) -> File {
+    let tags = tags.iter().map(|t| Tag::from_str(t).unwrap()).collect();
+
+    client
+        .upload(
+            name.to_string(),
+            tags,
+            replace,
+            content.len() as u64,
+            None,
+            content,
+        )
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[allow(dead_code)]
+pub async fn read_all(client: &Client, file: &File) -> Vec<u8> {
+    let mut stream = client
+        .stream(file.hash.clone(), 0, file.size)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut data = vec![];
+    stream.read_to_end(&mut data).await.unwrap();
+    data
+}
+
+async fn connect(sk: &SecretKey, server_addr: NodeAddr) -> Client {
+    let endpoint = Endpoint::builder()
+        .discovery_n0()
+        .secret_key(sk.clone())
+        .bind()
+        .await
+        .unwrap();
+
+    Client::with_addr(endpoint, server_addr)
+}
+
+#[allow(dead_code)]
+async fn write_frame(tx: &mut SendStream, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    tx.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    tx.write_all(data).await?;
+    Ok(())
 }
@@ -0,0 +1,62 @@
+use iroh::{Endpoint, Watcher};
+use stash::{CAPABILITIES, Hello, PROTOCOL_VERSION, ServerInfo};
+use util::{ClientServer, TestInfra};
+
+mod util;
+
+#[tokio::test]
+async fn server_info() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = client_server.client;
+
+    let info = client.server_info().await.unwrap();
+    assert_eq!(info.version, PROTOCOL_VERSION);
+    assert_eq!(info.capabilities, CAPABILITIES.to_vec());
+    assert!(info.supports("stream"));
+    assert!(!info.supports("teleport"));
+}
+
+#[tokio::test]
+async fn incompatible_client() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+
+    let endpoint = Endpoint::builder()
+        .discovery_n0()
+        .secret_key(client_server.client_sk.clone())
+        .bind()
+        .await
+        .unwrap();
+
+    let server_addr = client_server
+        .server
+        .endpoint()
+        .node_addr()
+        .initialized()
+        .await;
+
+    let conn = endpoint.connect(server_addr, stash::ALPN).await.unwrap();
+    let (mut tx, mut rx) = conn.open_bi().await.unwrap();
+
+    let config = bincode::config::standard();
+    let hello = Hello {
+        version: PROTOCOL_VERSION + 1,
+    };
+    let hello = bincode::encode_to_vec(&hello, config).unwrap();
+    tx.write_all(&(hello.len() as u32).to_be_bytes())
+        .await
+        .unwrap();
+    tx.write_all(&hello).await.unwrap();
+    tx.finish().unwrap();
+
+    let mut len = [0u8; 4];
+    rx.read_exact(&mut len).await.unwrap();
+    let mut data = vec![0; u32::from_be_bytes(len) as usize];
+    rx.read_exact(&mut data).await.unwrap();
+
+    let info: ServerInfo = bincode::decode_from_slice(&data, config).unwrap().0;
+    assert_eq!(info, ServerInfo::current());
+
+    conn.close(0u32.into(), b"bye");
+}
@@ -0,0 +1,144 @@
+use std::str::FromStr;
+
+use stash::{Failure, Tag};
+use util::{ClientServer, TestInfra, create_file};
+
+mod util;
+
+#[tokio::test]
+async fn file_versions() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::new(infra).await;
+    let client = &client_server.client;
+
+    let file1 = create_file(client, "doc", &["draft"], false, b"one").await;
+    let file2 = create_file(client, "doc", &["review"], true, b"two").await;
+    let file3 = create_file(client, "doc", &["final"], true, b"three").await;
+
+    let current = client.describe("doc".to_string()).await.unwrap().unwrap();
+    assert_eq!(current.hash, file3.hash);
+
+    let versions = client.versions("doc".to_string()).await.unwrap().unwrap();
+    let summary: Vec<_> = versions
+        .iter()
+        .map(|v| (v.version, v.hash.clone(), v.tags.clone(), v.current))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (3, file3.hash.clone(), vec!["final".to_string()], true),
+            (2, file2.hash.clone(), vec!["review".to_string()], false),
+            (1, file1.hash.clone(), vec!["draft".to_string()], false),
+        ]
+    );
+
+    let data = client.download(file1.hash.clone(), 0, 3).await.unwrap();
+    assert_eq!(data.unwrap(), b"one".to_vec());
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.files, 1);
+    assert_eq!(stats.contents, 3);
+    assert_eq!(stats.logical_size, 5);
+
+    let draft = Tag::from_str("draft").unwrap();
+    let files = client.list(draft.clone(), None).await.unwrap().unwrap();
+    assert!(files.is_empty());
+
+    let restored = client
+        .restore_version("doc".to_string(), 1)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(restored.hash, file1.hash);
+
+    let files = client.list(draft, None).await.unwrap().unwrap();
+    assert_eq!(files, vec![restored]);
+
+    let versions = client.versions("doc".to_string()).await.unwrap().unwrap();
+    assert_eq!(versions.len(), 4);
+    assert_eq!(versions[0].version, 4);
+    assert_eq!(versions[0].hash, file1.hash);
+
+    let rsp = client.restore_version("doc".to_string(), 4).await.unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::Conflict("Version 4 is already current".to_string())
+    );
+
+    let rsp = client.restore_version("doc".to_string(), 9).await.unwrap();
+    assert_eq!(rsp.err(), Failure::NotFound("No such version".to_string()));
+
+    client.delete("doc".to_string()).await.unwrap().unwrap();
+    client.purge_trash(None).await.unwrap().unwrap();
+
+    let rsp = client.versions("doc".to_string()).await.unwrap();
+    assert_eq!(rsp.err(), Failure::NotFound("No such file".to_string()));
+    assert!(client_server.infra.files().await.is_empty());
+
+    let file = create_file(client, "doc", &["draft"], false, b"again").await;
+    let versions = client.versions("doc".to_string()).await.unwrap().unwrap();
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].version, 1);
+    assert_eq!(versions[0].hash, file.hash);
+}
+
+#[tokio::test]
+async fn version_pruning() {
+    let infra = TestInfra::new().await;
+    let client_server = ClientServer::configure(infra, |s| s.with_max_versions(3)).await;
+    let client = &client_server.client;
+
+    let mut files = vec![create_file(client, "log", &["test"], false, b"v1").await];
+    for content in [b"v2", b"v3", b"v4"] {
+        files.push(create_file(client, "log", &["test"], true, content).await);
+    }
+
+    let versions = client.versions("log".to_string()).await.unwrap().unwrap();
+    let numbers: Vec<_> = versions.iter().map(|v| v.version).collect();
+    assert_eq!(numbers, vec![4, 3, 2]);
+
+    let mut expected: Vec<_> = files[1..].iter().map(|f| f.hash.clone()).collect();
+    expected.sort();
+    assert_eq!(client_server.infra.files().await, expected);
+
+    let rsp = client
+        .set_version_limit("log".to_string(), Some(0))
+        .await
+        .unwrap();
+    assert_eq!(
+        rsp.err(),
+        Failure::InvalidArgument("At least one version must be kept".to_string())
+    );
+
+    client
+        .set_version_limit("log".to_string(), Some(1))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let versions = client.versions("log".to_string()).await.unwrap().unwrap();
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].version, 4);
+    assert_eq!(
+        client_server.infra.files().await,
+        vec![files[3].hash.clone()]
+    );
+
+    let stats = client.stats().await.unwrap().unwrap();
+    assert_eq!(stats.contents, 1);
+
+    client
+        .set_version_limit("log".to_string(), None)
+        .await
+        .unwrap()
+        .unwrap();
+    create_file(client, "log", &["test"], true, b"v5").await;
+    let versions = client.versions("log".to_string()).await.unwrap().unwrap();
+    assert_eq!(versions.len(), 2);
+
+    let rsp = client
+        .set_version_limit("missing".to_string(), Some(2))
+        .await
+        .unwrap();
+    assert_eq!(rsp.err(), Failure::NotFound("No such file".to_string()));
+}